@@ -0,0 +1,392 @@
+// Who is calling and what they may do. Reads are always anonymous; writes need a principal with
+// the write permission whose chart scope covers the chart being changed, unless anonymous writes
+// were explicitly allowed.
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use axum::http::{header, HeaderMap};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::chart::spec;
+use crate::config::AuthConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Permission {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Principal {
+    pub subject: String,
+    pub permissions: Vec<Permission>,
+    pub charts: Option<Vec<String>>, // Chart-name prefixes; `None` covers every chart.
+}
+
+impl Principal {
+    pub fn anonymous() -> Principal {
+        Principal {
+            subject: String::from("anonymous"),
+            permissions: vec![Permission::Read],
+            charts: None,
+        }
+    }
+
+    pub fn can(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
+
+    // Scopes are name prefixes, so only a valid chart name can be matched against them.
+    pub fn can_write(&self, chart: &str) -> bool {
+        self.can(Permission::Write)
+            && spec::validate_name(chart).is_ok()
+            && match &self.charts {
+                Some(prefixes) => prefixes.iter().any(|p| chart.starts_with(p.as_str())),
+                None => true,
+            }
+    }
+}
+
+// One way of proving who you are. `None` means the credentials are not for this authenticator,
+// so the next one gets a go; `Some(Err)` means they are, and they are wrong.
+pub trait Authenticator: Send + Sync {
+    fn authenticate(&self, scheme: &str, credentials: &str) -> Option<Result<Principal, String>>;
+}
+
+pub struct BearerTokens {
+    tokens: Vec<String>,
+}
+
+impl Authenticator for BearerTokens {
+    fn authenticate(&self, scheme: &str, credentials: &str) -> Option<Result<Principal, String>> {
+        if !scheme.eq_ignore_ascii_case("bearer") {
+            return None;
+        }
+        // Every token is compared, in full, so the time taken tells nothing about any of them.
+        let known = self
+            .tokens
+            .iter()
+            .fold(false, |known, token| known | same_token(token, credentials));
+        if !known {
+            return None;
+        }
+
+        Some(Ok(Principal {
+            subject: String::from("bearer-token"),
+            permissions: vec![Permission::Read, Permission::Write],
+            charts: None,
+        }))
+    }
+}
+
+// Compares digests, which have the same length whatever the tokens, without stopping at the
+// first byte that differs.
+fn same_token(a: &str, b: &str) -> bool {
+    let (a, b) = (Sha256::digest(a), Sha256::digest(b));
+    a.iter()
+        .zip(b.iter())
+        .fold(0, |diff, (x, y)| diff | (x ^ y))
+        == 0
+}
+
+// An Apache htpasswd file. Only bcrypt entries (`htpasswd -B`) are accepted.
+pub struct Htpasswd {
+    users: BTreeMap<String, String>,
+    scopes: BTreeMap<String, Vec<String>>,
+}
+
+impl Htpasswd {
+    pub fn from_file(
+        path: &Path,
+        scopes: &BTreeMap<String, Vec<String>>,
+    ) -> Result<Htpasswd, Box<dyn Error>> {
+        let mut users = BTreeMap::new();
+        for (number, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (user, hash) = line
+                .split_once(':')
+                .ok_or_else(|| format!("line {} is not `user:hash`", number + 1))?;
+            if !hash.starts_with("$2") {
+                return Err(format!("user {user} does not have a bcrypt hash").into());
+            }
+            users.insert(user.to_string(), hash.to_string());
+        }
+
+        Ok(Htpasswd {
+            users,
+            scopes: scopes.clone(),
+        })
+    }
+}
+
+impl Authenticator for Htpasswd {
+    fn authenticate(&self, scheme: &str, credentials: &str) -> Option<Result<Principal, String>> {
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return None;
+        }
+
+        let decoded = STANDARD
+            .decode(credentials)
+            .ok()
+            .and_then(|d| String::from_utf8(d).ok());
+        let Some((user, password)) = decoded.as_deref().and_then(|d| d.split_once(':')) else {
+            return Some(Err(String::from("malformed basic credentials")));
+        };
+        let hash = self.users.get(user)?;
+
+        match bcrypt::verify(password, hash) {
+            Ok(true) => Some(Ok(Principal {
+                subject: user.to_string(),
+                permissions: vec![Permission::Read, Permission::Write],
+                charts: self.scopes.get(user).cloned(),
+            })),
+            _ => Some(Err(format!("wrong password for {user}"))),
+        }
+    }
+}
+
+// JWTs signed with HS256 (a shared secret), RS256 or ES256 (a PEM public key). Write access
+// comes from `write` in the space-separated `scope` claim, and an optional `charts` claim
+// narrows the chart prefixes the token may publish under.
+pub struct Jwt {
+    key: DecodingKey,
+    validation: Validation,
+    scopes: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: String,
+    #[serde(default)]
+    scope: String,
+    charts: Option<Vec<String>>,
+}
+
+impl Jwt {
+    pub fn from_file(
+        path: &Path,
+        scopes: &BTreeMap<String, Vec<String>>,
+    ) -> Result<Jwt, Box<dyn Error>> {
+        let pem = fs::read(path)?;
+        let text = String::from_utf8_lossy(&pem);
+
+        let (key, algorithm) = if let Ok(key) = DecodingKey::from_ec_pem(&pem) {
+            (key, Algorithm::ES256)
+        } else if let Ok(key) = DecodingKey::from_rsa_pem(&pem) {
+            (key, Algorithm::RS256)
+        } else if text.contains("-----BEGIN") {
+            return Err("only RSA and EC public keys are supported".into());
+        } else {
+            (
+                DecodingKey::from_secret(text.trim().as_bytes()),
+                Algorithm::HS256,
+            )
+        };
+
+        let mut validation = Validation::new(algorithm);
+        validation.set_required_spec_claims(&["exp", "sub"]);
+
+        Ok(Jwt {
+            key,
+            validation,
+            scopes: scopes.clone(),
+        })
+    }
+}
+
+impl Authenticator for Jwt {
+    fn authenticate(&self, scheme: &str, credentials: &str) -> Option<Result<Principal, String>> {
+        // Opaque bearer tokens are not JWTs; leave them to the other authenticators.
+        if !scheme.eq_ignore_ascii_case("bearer") || credentials.split('.').count() != 3 {
+            return None;
+        }
+
+        let claims = match jsonwebtoken::decode::<Claims>(credentials, &self.key, &self.validation)
+        {
+            Ok(data) => data.claims,
+            Err(err) => return Some(Err(format!("invalid token: {err}"))),
+        };
+
+        let mut permissions = vec![Permission::Read];
+        if claims.scope.split(' ').any(|s| s == "write") {
+            permissions.push(Permission::Write);
+        }
+        let charts = claims
+            .charts
+            .or_else(|| self.scopes.get(&claims.sub).cloned());
+
+        Some(Ok(Principal {
+            subject: claims.sub,
+            permissions,
+            charts,
+        }))
+    }
+}
+
+pub struct Auth {
+    authenticators: Vec<Box<dyn Authenticator>>,
+    allow_anonymous_writes: bool,
+}
+
+impl Auth {
+    pub fn from_config(config: &AuthConfig) -> Result<Auth, Box<dyn Error>> {
+        let mut authenticators: Vec<Box<dyn Authenticator>> = Vec::new();
+
+        if !config.bearer_tokens.is_empty() {
+            authenticators.push(Box::new(BearerTokens {
+                tokens: config.bearer_tokens.clone(),
+            }));
+        }
+        if let Some(path) = &config.htpasswd_file {
+            authenticators.push(Box::new(
+                Htpasswd::from_file(path, &config.scopes)
+                    .map_err(|e| format!("{}: {e}", path.display()))?,
+            ));
+        }
+        if let Some(path) = &config.jwt_key_file {
+            authenticators.push(Box::new(
+                Jwt::from_file(path, &config.scopes)
+                    .map_err(|e| format!("{}: {e}", path.display()))?,
+            ));
+        }
+
+        Ok(Auth {
+            authenticators,
+            allow_anonymous_writes: config.allow_anonymous_writes,
+        })
+    }
+
+    // No Authorization header is anonymous; one nobody recognises is an error.
+    pub fn authenticate(&self, headers: &HeaderMap) -> Result<Principal, String> {
+        let Some(authorization) = headers.get(header::AUTHORIZATION) else {
+            let mut anonymous = Principal::anonymous();
+            if self.allow_anonymous_writes {
+                anonymous.permissions.push(Permission::Write);
+            }
+            return Ok(anonymous);
+        };
+        let authorization = authorization
+            .to_str()
+            .map_err(|_| String::from("malformed Authorization header"))?;
+        let (scheme, credentials) = authorization
+            .split_once(' ')
+            .ok_or_else(|| String::from("malformed Authorization header"))?;
+
+        self.authenticators
+            .iter()
+            .find_map(|a| a.authenticate(scheme, credentials.trim()))
+            .unwrap_or_else(|| Err(String::from("unknown credentials")))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use axum::http::{header, HeaderMap, HeaderValue};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    use super::{Auth, BearerTokens, Htpasswd, Jwt, Permission};
+    use crate::config::AuthConfig;
+
+    fn headers(authorization: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(authorization).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn test_authenticators_and_chart_scopes() {
+        let scopes = BTreeMap::from([(String::from("team-a"), vec![String::from("team-a-")])]);
+        let hash = bcrypt::hash("secret", 4).unwrap();
+        let auth = Auth {
+            authenticators: vec![
+                Box::new(BearerTokens {
+                    tokens: vec![String::from("ci-token")],
+                }),
+                Box::new(Htpasswd {
+                    users: BTreeMap::from([(String::from("team-a"), hash)]),
+                    scopes: scopes.clone(),
+                }),
+                Box::new(Jwt {
+                    key: jsonwebtoken::DecodingKey::from_secret(b"jwt-secret"),
+                    validation: jsonwebtoken::Validation::default(),
+                    scopes,
+                }),
+            ],
+            allow_anonymous_writes: false,
+        };
+
+        let anonymous = auth.authenticate(&HeaderMap::new()).unwrap();
+        assert!(anonymous.can(Permission::Read));
+        assert!(!anonymous.can_write("anything"));
+
+        let ci = auth.authenticate(&headers("Bearer ci-token")).unwrap();
+        assert!(ci.can_write("anything"));
+        assert!(auth.authenticate(&headers("Bearer wrong")).is_err());
+
+        let basic = format!("Basic {}", STANDARD.encode("team-a:secret"));
+        let team = auth.authenticate(&headers(&basic)).unwrap();
+        assert!(team.can_write("team-a-api"));
+        assert!(!team.can_write("team-b-api"));
+        assert!(!team.can_write("team-a-/../team-b-api"));
+        let wrong = format!("Basic {}", STANDARD.encode("team-a:nope"));
+        assert!(auth.authenticate(&headers(&wrong)).is_err());
+
+        let token = |claims: serde_json::Value| {
+            let key = EncodingKey::from_secret(b"jwt-secret");
+            format!(
+                "Bearer {}",
+                jsonwebtoken::encode(&Header::default(), &claims, &key).unwrap()
+            )
+        };
+        let exp = chrono::Utc::now().timestamp() + 60;
+        let reader = auth
+            .authenticate(&headers(&token(json!({ "sub": "bot", "exp": exp }))))
+            .unwrap();
+        assert!(!reader.can_write("anything"));
+        let scoped = auth
+            .authenticate(&headers(&token(
+                json!({ "sub": "team-a", "exp": exp, "scope": "read write" }),
+            )))
+            .unwrap();
+        assert!(scoped.can_write("team-a-api"));
+        assert!(!scoped.can_write("team-b-api"));
+        let expired = token(json!({ "sub": "bot", "exp": exp - 3600, "scope": "write" }));
+        assert!(auth.authenticate(&headers(&expired)).is_err());
+    }
+
+    #[test]
+    fn test_writes_are_closed_unless_allowed() {
+        let config = AuthConfig::default();
+        let auth = Auth::from_config(&config).unwrap();
+        assert!(!auth
+            .authenticate(&HeaderMap::new())
+            .unwrap()
+            .can_write("app"));
+        assert!(auth.authenticate(&headers("Bearer anything")).is_err());
+
+        let config = AuthConfig {
+            allow_anonymous_writes: true,
+            ..Default::default()
+        };
+        let auth = Auth::from_config(&config).unwrap();
+        let anonymous = auth.authenticate(&HeaderMap::new()).unwrap();
+        assert!(anonymous.can_write("app"));
+        assert!(!anonymous.can_write("../app"));
+    }
+}
@@ -0,0 +1,750 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use glob::glob;
+use reqwest::Client;
+use serde::Serialize;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use crate::chart::spec::{self, Chart, Conflict, Repository, RepositoryEntry, Version};
+use crate::chart::{artifacthub, merger, package, provenance};
+use crate::config::{ChartsConfig, Source};
+use crate::mirror::{self, MirroredChart};
+use crate::pgp::{Keyring, SecretKey};
+
+#[derive(Debug, Clone)]
+pub struct ChartPackage {
+    pub chart: Chart,
+    pub chart_yaml: String, // The merged descriptor, exactly as packaged.
+    pub archive: Vec<u8>,
+    pub digest: String,
+    pub provenance: Option<String>,
+    pub created: String,
+    pub descriptor: Option<PathBuf>, // The Chart.yaml it was built from; `None` for uploads.
+}
+
+// `generation` goes up with every change so caches of anything derived from the catalog know
+// when to throw their copy away; `modified` is when that last happened.
+#[derive(Debug)]
+pub struct Catalog {
+    pub packages: Vec<ChartPackage>,
+    pub mirrored: Vec<MirroredChart>, // Upstream charts, in the order upstreams are configured.
+    pub generation: u64,
+    pub modified: SystemTime,
+}
+
+// Where a load pass found a chart wrong: reading its files, parsing the YAML, merging the override
+// stack, validating the result against the chart schema or packaging it. A conflict is a chart
+// version that another source with a higher priority offers with a different archive. Lint
+// problems do not keep a chart out; they are annotations Artifact Hub would not understand.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    Read,
+    Parse,
+    Merge,
+    Validate,
+    Package,
+    Conflict,
+    Lint,
+}
+
+// Whether a chart was left out of the catalog (or one of two archives for a version dropped), or
+// is served with a warning.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+// Something a load pass found wrong with a chart, reported at /api/errors and through readiness.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct LoadError {
+    pub path: String,
+    pub stage: Stage,
+    pub severity: Severity,
+    pub error: String,
+}
+
+impl Stage {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Stage::Lint => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl std::fmt::Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let stage = match self {
+            Stage::Read => "read",
+            Stage::Parse => "parse",
+            Stage::Merge => "merge",
+            Stage::Validate => "validate",
+            Stage::Package => "package",
+            Stage::Conflict => "conflict",
+            Stage::Lint => "lint",
+        };
+        f.write_str(stage)
+    }
+}
+
+impl LoadError {
+    fn new(path: &Path, stage: Stage, error: impl ToString) -> LoadError {
+        LoadError::at(path.display().to_string(), stage, error)
+    }
+
+    // For errors about something that is not a file: a URL or an archive name.
+    pub fn at(path: String, stage: Stage, error: impl ToString) -> LoadError {
+        LoadError {
+            path,
+            stage,
+            severity: stage.severity(),
+            error: error.to_string(),
+        }
+    }
+}
+
+impl From<Conflict> for LoadError {
+    fn from(conflict: Conflict) -> LoadError {
+        LoadError::at(
+            archive_name(&conflict.name, &conflict.version),
+            Stage::Conflict,
+            conflict,
+        )
+    }
+}
+
+// Where a download is served from: a package held in memory or an upstream archive.
+pub enum Resolved<'a> {
+    Package(&'a ChartPackage),
+    Mirrored(&'a MirroredChart),
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}): {}", self.path, self.stage, self.error)
+    }
+}
+
+// The outcome of a load pass: every chart that made it, and why the others did not.
+#[derive(Debug, Default)]
+pub struct LoadReport {
+    pub packages: Vec<ChartPackage>,
+    pub mirrored: Vec<MirroredChart>,
+    pub errors: Vec<LoadError>,
+}
+
+pub fn archive_name(name: &str, version: &Version) -> String {
+    format!("{name}-{version}.tgz")
+}
+
+// The archive name, once it is safe to join to a folder: the version's prerelease part is free
+// text, so it is checked too.
+pub fn checked_archive_name(name: &str, version: &Version) -> Result<String, String> {
+    spec::validate_name(name)?;
+    let file_name = archive_name(name, version);
+    if file_name.contains(['/', '\\']) || file_name.contains("..") {
+        return Err(format!(
+            "version `{version}` of {name} cannot be a file name"
+        ));
+    }
+    Ok(file_name)
+}
+
+impl ChartPackage {
+    pub fn lint(&self) -> Vec<LoadError> {
+        let path = match &self.descriptor {
+            Some(descriptor) => descriptor.display().to_string(),
+            None => self.file_name(),
+        };
+        artifacthub::lint(&self.chart.annotations)
+            .into_iter()
+            .map(|error| LoadError::at(path.clone(), Stage::Lint, error))
+            .collect()
+    }
+
+    pub fn file_name(&self) -> String {
+        archive_name(&self.chart.name, &self.chart.version)
+    }
+
+    pub fn modified(&self) -> SystemTime {
+        DateTime::parse_from_rfc3339(&self.created)
+            .map(SystemTime::from)
+            .unwrap_or(SystemTime::UNIX_EPOCH)
+    }
+
+    // Uploaded provenance is checked whenever a keyring is configured; without one it is kept
+    // as-is so that `helm install --verify` can still check it client side.
+    pub fn from_upload(
+        archive: Vec<u8>,
+        provenance: Option<String>,
+        keyring: Option<&Keyring>,
+        required: bool,
+    ) -> Result<ChartPackage, Box<dyn Error>> {
+        let chart_yaml = package::chart_yaml_from_archive(&archive)?;
+        let chart: Chart = serde_yaml::from_str(&chart_yaml)?;
+        let digest = package::digest(&archive);
+        let file_name = checked_archive_name(&chart.name, &chart.version)?;
+
+        match (&provenance, keyring) {
+            (Some(prov), Some(keyring)) => provenance::verify(keyring, prov, &file_name, &digest)?,
+            (None, _) if required => {
+                return Err(format!("{file_name} must be uploaded with its provenance file").into())
+            }
+            _ => {}
+        }
+
+        Ok(ChartPackage {
+            chart,
+            chart_yaml,
+            archive,
+            digest,
+            provenance,
+            created: timestamp(SystemTime::now()),
+            descriptor: None,
+        })
+    }
+}
+
+impl Catalog {
+    pub fn new(packages: Vec<ChartPackage>) -> Catalog {
+        Catalog {
+            packages,
+            mirrored: Vec::new(),
+            generation: 1,
+            modified: SystemTime::now(),
+        }
+    }
+
+    pub fn insert(&mut self, package: ChartPackage) {
+        self.packages.push(package);
+        self.touch();
+    }
+
+    // Reloads usually find the same charts, so the index (and its validators) only moves on
+    // when some archive or provenance actually changed.
+    pub fn replace(&mut self, packages: Vec<ChartPackage>, mirrored: Vec<MirroredChart>) {
+        let unchanged = packages.len() == self.packages.len()
+            && packages
+                .iter()
+                .zip(&self.packages)
+                .all(|(new, old)| new.digest == old.digest && new.provenance == old.provenance)
+            && mirrored == self.mirrored;
+        self.packages = packages;
+        self.mirrored = mirrored;
+        if !unchanged {
+            self.touch();
+        }
+    }
+
+    pub fn remove(&mut self, file_name: &str) -> Option<ChartPackage> {
+        let position = self
+            .packages
+            .iter()
+            .position(|p| p.file_name() == file_name)?;
+        let package = self.packages.remove(position);
+        self.touch();
+        Some(package)
+    }
+
+    fn touch(&mut self) {
+        self.generation += 1;
+        self.modified = SystemTime::now();
+    }
+
+    pub fn index(&self, base_url: &str, priority: &[Source]) -> Repository {
+        let (mut index, _) = Repository::merge(self.sources(priority));
+        index.generated = timestamp(self.modified);
+        for entry in index.entries.values_mut().flatten() {
+            entry.urls = vec![format!(
+                "{base_url}/charts/{}",
+                archive_name(&entry.name, &entry.version)
+            )];
+        }
+        index
+    }
+
+    pub fn conflicts(&self, priority: &[Source]) -> Vec<Conflict> {
+        Repository::merge(self.sources(priority)).1
+    }
+
+    // Mirrored charts are someone else's to fix.
+    pub fn lint(&self) -> Vec<LoadError> {
+        self.packages.iter().flat_map(ChartPackage::lint).collect()
+    }
+
+    // Every source as its own index, highest priority first.
+    fn sources(&self, priority: &[Source]) -> Vec<(String, Repository)> {
+        let mut sources = Vec::new();
+        for source in priority {
+            match source {
+                Source::Local | Source::Uploads => {
+                    let mut index = Repository::new();
+                    for package in self.packages(*source) {
+                        let mut entry = RepositoryEntry::from(&package.chart);
+                        entry.created = package.created.clone();
+                        entry.digest = package.digest.clone();
+                        index.add(entry);
+                    }
+                    sources.push((source.to_string(), index));
+                }
+                Source::Upstreams => {
+                    for chart in &self.mirrored {
+                        if sources.last().map(|(url, _)| url) != Some(&chart.upstream) {
+                            sources.push((chart.upstream.clone(), Repository::new()));
+                        }
+                        if let Some((_, index)) = sources.last_mut() {
+                            index.add(chart.entry.clone());
+                        }
+                    }
+                }
+            }
+        }
+        sources
+    }
+
+    fn packages(&self, source: Source) -> impl Iterator<Item = &ChartPackage> {
+        let uploaded = source == Source::Uploads;
+        self.packages
+            .iter()
+            .filter(move |p| p.descriptor.is_none() == uploaded)
+    }
+
+    // The archive behind a file name in the index, taken from the source that won it.
+    pub fn resolve(&self, file_name: &str, priority: &[Source]) -> Option<Resolved<'_>> {
+        priority.iter().find_map(|source| match source {
+            Source::Upstreams => self.find_mirrored(file_name).map(Resolved::Mirrored),
+            _ => self
+                .packages(*source)
+                .find(|p| p.file_name() == file_name)
+                .map(Resolved::Package),
+        })
+    }
+
+    pub fn find(&self, file_name: &str) -> Option<&ChartPackage> {
+        self.packages.iter().find(|p| p.file_name() == file_name)
+    }
+
+    pub fn find_mirrored(&self, file_name: &str) -> Option<&MirroredChart> {
+        self.mirrored.iter().find(|c| c.file_name() == file_name)
+    }
+}
+
+// Everything a repository serves: its charts, packaged from the chart roots or uploaded, and the
+// charts of its upstreams.
+pub async fn load(charts: &ChartsConfig, signer: Option<&SecretKey>, http: &Client) -> LoadReport {
+    let mut report = merge_charts(charts, signer).await;
+    for upstream in &charts.upstreams {
+        let (mirrored, errors) = mirror::load(http, upstream).await;
+        report.mirrored.extend(mirrored);
+        report.errors.extend(errors);
+    }
+    report
+}
+
+// A broken chart is left out and reported; it never keeps the others from loading. Charts are
+// packaged on the blocking pool, a few at a time, and come back in glob order whatever order
+// they finish in.
+pub async fn merge_charts(charts: &ChartsConfig, signer: Option<&SecretKey>) -> LoadReport {
+    let mut report = LoadReport::default();
+    let mut paths = Vec::new();
+
+    for root in &charts.roots {
+        let pattern = root.join("*").join(&charts.descriptor_file);
+        let found = match glob(&pattern.to_string_lossy()) {
+            Ok(found) => found,
+            Err(err) => {
+                report
+                    .errors
+                    .push(LoadError::new(&pattern, Stage::Read, err));
+                continue;
+            }
+        };
+
+        for path in found {
+            match path {
+                Ok(path_buf) => paths.push(path_buf),
+                Err(err) => {
+                    report
+                        .errors
+                        .push(LoadError::new(err.path(), Stage::Read, err.error()))
+                }
+            }
+        }
+    }
+
+    let overrides = Arc::new(charts.overrides.clone());
+    let signer = Arc::new(signer.cloned());
+    let limit = Arc::new(Semaphore::new(load_concurrency()));
+    let mut tasks = JoinSet::new();
+
+    for (i, path) in paths.iter().cloned().enumerate() {
+        let permit = limit.clone().acquire_owned().await;
+        let (overrides, signer) = (overrides.clone(), signer.clone());
+        tasks.spawn_blocking(move || {
+            let _permit = permit;
+            (
+                i,
+                package_chart(&overrides, signer.as_ref().as_ref(), &path),
+            )
+        });
+    }
+
+    let mut results: Vec<Option<Result<ChartPackage, LoadError>>> = vec![None; paths.len()];
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok((i, result)) = joined {
+            results[i] = Some(result);
+        }
+    }
+
+    for (path, result) in paths.iter().zip(results) {
+        match result {
+            Some(Ok(package)) => report.packages.push(package),
+            Some(Err(err)) => report.errors.push(err),
+            None => report.errors.push(LoadError::new(
+                path,
+                Stage::Package,
+                "loading the chart panicked",
+            )),
+        }
+    }
+
+    let uploads = charts.uploads.clone();
+    match tokio::task::spawn_blocking(move || load_uploads(&uploads)).await {
+        Ok(uploaded) => {
+            report.packages.extend(uploaded.packages);
+            report.errors.extend(uploaded.errors);
+        }
+        Err(err) => report
+            .errors
+            .push(LoadError::new(&charts.uploads, Stage::Read, err)),
+    }
+
+    report
+}
+
+fn load_concurrency() -> usize {
+    std::thread::available_parallelism().map_or(4, |n| n.get())
+}
+
+fn package_chart(
+    overrides: &[PathBuf],
+    signer: Option<&SecretKey>,
+    path: &Path,
+) -> Result<ChartPackage, LoadError> {
+    let mut layers = Vec::new();
+    for layer in merger::layer_paths(overrides, path) {
+        let content =
+            fs::read_to_string(&layer).map_err(|e| LoadError::new(&layer, Stage::Read, e))?;
+        let value: serde_yaml::Value =
+            serde_yaml::from_str(&content).map_err(|e| LoadError::new(&layer, Stage::Parse, e))?;
+        layers.push(value);
+    }
+
+    let value = merger::merge_layers(layers);
+    let chart: Chart =
+        serde_yaml::from_value(value).map_err(|e| LoadError::new(path, Stage::Validate, e))?;
+    let chart_yaml =
+        serde_yaml::to_string(&chart).map_err(|e| LoadError::new(path, Stage::Merge, e))?;
+
+    let archive = package::package(path, &chart.name, &chart_yaml)
+        .map_err(|e| LoadError::new(path, Stage::Package, e))?;
+    let digest = package::digest(&archive);
+    let created = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map_err(|e| LoadError::new(path, Stage::Read, e))?;
+    // Signed at the descriptor's mtime rather than now, so that reloading an unchanged chart
+    // gives the same provenance.
+    let provenance = match signer {
+        Some(key) => Some(
+            provenance::sign(
+                key,
+                &chart_yaml,
+                &archive_name(&chart.name, &chart.version),
+                &digest,
+                created,
+            )
+            .map_err(|e| LoadError::new(path, Stage::Package, e))?,
+        ),
+        None => None,
+    };
+
+    Ok(ChartPackage {
+        chart,
+        chart_yaml,
+        archive,
+        digest,
+        provenance,
+        created: timestamp(created),
+        descriptor: Some(path.to_path_buf()),
+    })
+}
+
+fn load_uploads(dir: &Path) -> LoadReport {
+    let mut report = LoadReport::default();
+    let Ok(paths) = glob(&dir.join("*.tgz").to_string_lossy()) else {
+        return report;
+    };
+
+    for path in paths.flatten() {
+        let load = || -> Result<ChartPackage, LoadError> {
+            let archive = fs::read(&path).map_err(|e| LoadError::new(&path, Stage::Read, e))?;
+            let chart_yaml = package::chart_yaml_from_archive(&archive)
+                .map_err(|e| LoadError::new(&path, Stage::Read, e))?;
+            let created = fs::metadata(&path)
+                .and_then(|m| m.modified())
+                .map_err(|e| LoadError::new(&path, Stage::Read, e))?;
+            Ok(ChartPackage {
+                chart: serde_yaml::from_str(&chart_yaml)
+                    .map_err(|e| LoadError::new(&path, Stage::Validate, e))?,
+                chart_yaml,
+                digest: package::digest(&archive),
+                archive,
+                provenance: fs::read_to_string(path.with_extension("tgz.prov")).ok(),
+                created: timestamp(created),
+                descriptor: None,
+            })
+        };
+
+        match load() {
+            Ok(package) => report.packages.push(package),
+            Err(err) => report.errors.push(err),
+        }
+    }
+
+    report
+}
+
+pub fn delete_upload(dir: &Path, package: &ChartPackage) -> std::io::Result<()> {
+    let archive = dir.join(upload_name(package)?);
+    fs::remove_file(&archive)?;
+    match fs::remove_file(archive.with_extension("tgz.prov")) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+pub fn store_upload(dir: &Path, package: &ChartPackage) -> std::io::Result<()> {
+    let archive = dir.join(upload_name(package)?);
+    fs::create_dir_all(dir)?;
+    fs::write(&archive, &package.archive)?;
+    if let Some(prov) = &package.provenance {
+        fs::write(archive.with_extension("tgz.prov"), prov)?;
+    }
+
+    Ok(())
+}
+
+// Checked again here, whatever made the package, so nothing is written or removed outside `dir`.
+fn upload_name(package: &ChartPackage) -> std::io::Result<String> {
+    checked_archive_name(&package.chart.name, &package.chart.version)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+}
+
+fn timestamp(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Nanos, true)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+
+    use reqwest::Url;
+
+    use super::{
+        delete_upload, merge_charts, store_upload, Catalog, ChartPackage, Resolved, Severity, Stage,
+    };
+    use crate::chart::package;
+    use crate::chart::spec::{Chart, RepositoryEntry};
+    use crate::config::{ChartsConfig, Source};
+    use crate::mirror::MirroredChart;
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_broken_charts_are_reported_not_fatal() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        let root = dir.join("charts");
+        let overrides = dir.join("local");
+
+        write(
+            &root.join("good/Chart.yaml"),
+            "apiVersion: v2\nname: good\ndescription: ok\ntype: application\nversion: 0.1.0-slug\n",
+        );
+        write(
+            &root.join("bad-override/Chart.yaml"),
+            "apiVersion: v2\nname: bad-override\ndescription: ok\ntype: application\nversion: 0.1.0-slug\n",
+        );
+        write(
+            &overrides
+                .join(root.strip_prefix("/").unwrap())
+                .join("bad-override/Chart.yaml"),
+            "version: [oops\n",
+        );
+        write(
+            &root.join("no-version/Chart.yaml"),
+            "apiVersion: v2\nname: no-version\n",
+        );
+
+        let charts = ChartsConfig {
+            roots: vec![root.clone()],
+            overrides: vec![overrides],
+            uploads: dir.join("uploads"),
+            ..Default::default()
+        };
+
+        let report = merge_charts(&charts, None).await;
+
+        let names: Vec<&str> = report
+            .packages
+            .iter()
+            .map(|p| p.chart.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["good"]);
+
+        let stages: Vec<(&str, Stage)> = report
+            .errors
+            .iter()
+            .map(|e| (e.path.rsplit('/').nth(1).unwrap(), e.stage))
+            .collect();
+        assert_eq!(
+            stages,
+            vec![
+                ("bad-override", Stage::Parse),
+                ("no-version", Stage::Validate)
+            ]
+        );
+        assert!(report.errors.iter().all(|e| e.severity == Severity::Error));
+        // Lint findings leave the chart served.
+        assert_eq!(Stage::Lint.severity(), Severity::Warning);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_loading_keeps_glob_order() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+
+        let mut expected = Vec::new();
+        for i in 0..40 {
+            let name = format!("chart-{i:02}");
+            write(
+                &dir.join("charts").join(&name).join("Chart.yaml"),
+                &format!("apiVersion: v2\nname: {name}\ndescription: ok\ntype: application\nversion: 0.1.0-slug\n"),
+            );
+            expected.push(name);
+        }
+
+        let charts = ChartsConfig {
+            roots: vec![dir.join("charts")],
+            uploads: dir.join("uploads"),
+            ..Default::default()
+        };
+
+        let report = merge_charts(&charts, None).await;
+
+        let names: Vec<String> = report.packages.into_iter().map(|p| p.chart.name).collect();
+        assert_eq!(names, expected);
+        assert!(report.errors.is_empty());
+    }
+
+    #[test]
+    fn test_source_priority_picks_the_served_archive() {
+        let chart: Chart = serde_yaml::from_str(
+            "apiVersion: v2\nname: demo\ndescription: d\ntype: application\nversion: 1.0.0\n",
+        )
+        .unwrap();
+        let mut entry = RepositoryEntry::from(&chart);
+        entry.digest = String::from("upstream-digest");
+
+        let mut catalog = Catalog::new(vec![ChartPackage {
+            chart,
+            chart_yaml: String::new(),
+            archive: Vec::new(),
+            digest: String::from("local-digest"),
+            provenance: None,
+            created: String::new(),
+            descriptor: Some("charts/demo/Chart.yaml".into()),
+        }]);
+        catalog.mirrored = vec![MirroredChart {
+            entry,
+            upstream: String::from("https://charts.example.com"),
+            source: Url::parse("https://charts.example.com/demo-1.0.0.tgz").unwrap(),
+            cache: "cache/demo-1.0.0.tgz".into(),
+            timeout: 10,
+        }];
+
+        let local_first = ChartsConfig::default().sources();
+        let index = catalog.index("http://localhost", &local_first);
+        assert_eq!(index.entries["demo"].len(), 1);
+        assert_eq!(index.entries["demo"][0].digest, "local-digest");
+        assert!(matches!(
+            catalog.resolve("demo-1.0.0.tgz", &local_first),
+            Some(Resolved::Package(_))
+        ));
+
+        let upstream_first = [Source::Upstreams, Source::Local, Source::Uploads];
+        let index = catalog.index("http://localhost", &upstream_first);
+        assert_eq!(index.entries["demo"][0].digest, "upstream-digest");
+        assert_eq!(
+            index.entries["demo"][0].urls,
+            vec!["http://localhost/charts/demo-1.0.0.tgz"]
+        );
+        assert!(matches!(
+            catalog.resolve("demo-1.0.0.tgz", &upstream_first),
+            Some(Resolved::Mirrored(_))
+        ));
+
+        let conflicts = catalog.conflicts(&upstream_first);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].kept, "https://charts.example.com");
+        assert_eq!(conflicts[0].dropped, "local");
+    }
+
+    #[test]
+    fn test_uploads_cannot_escape_their_folder() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        write(&dir.join("chart/Chart.yaml"), "");
+        let chart_yaml = "apiVersion: v2\nname: ../../../tmp/yws-escaped\nversion: 1.0.0-a\n";
+        let archive = package::package(&dir.join("chart/Chart.yaml"), "chart", chart_yaml).unwrap();
+
+        let err = ChartPackage::from_upload(archive.clone(), None, None, false).unwrap_err();
+        assert!(err.to_string().contains("chart name"), "{err}");
+
+        // Whatever built the package, the name and version are checked again before any path.
+        let mut package = ChartPackage {
+            chart: serde_yaml::from_str(chart_yaml).unwrap(),
+            chart_yaml: chart_yaml.to_string(),
+            archive,
+            digest: String::new(),
+            provenance: None,
+            created: String::new(),
+            descriptor: None,
+        };
+        let uploads = dir.join("uploads");
+        assert!(store_upload(&uploads, &package).is_err());
+        package.chart.name = String::from("escaped");
+        package.chart.version = "1.0.0-a/../../../b".parse().unwrap();
+        assert!(store_upload(&uploads, &package).is_err());
+        assert!(delete_upload(&uploads, &package).is_err());
+
+        let left: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(left, ["chart"]);
+    }
+}
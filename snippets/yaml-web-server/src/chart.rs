@@ -1,2 +1,13 @@
+pub mod artifacthub;
+pub mod bump;
+pub mod changelog;
+pub mod constraint;
+pub mod document;
+pub mod graph;
 pub mod merger;
+pub mod package;
+pub mod provenance;
+pub mod show;
 pub mod spec;
+pub mod values;
+pub mod vendor;
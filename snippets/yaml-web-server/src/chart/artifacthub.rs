@@ -0,0 +1,256 @@
+// Artifact Hub support: the artifacthub-repo.yml it reads from a repository, and a lint of the
+// `artifacthub.io/*` annotations it reads from each chart. Artifact Hub skips a chart, or the
+// broken annotation, without saying why, so problems are reported here instead.
+use std::collections::BTreeMap;
+
+use regex::Regex;
+use reqwest::Url;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use super::changelog;
+
+const PREFIX: &str = "artifacthub.io/";
+
+// The Helm annotations Artifact Hub knows about. Only some carry a format worth checking.
+const KNOWN: [&str; 16] = [
+    "alternativeName",
+    "category",
+    "changes",
+    "containsSecurityUpdates",
+    "crds",
+    "crdsExamples",
+    "images",
+    "license",
+    "links",
+    "maintainers",
+    "operator",
+    "operatorCapabilities",
+    "prerelease",
+    "recommendations",
+    "screenshots",
+    "signKey",
+];
+
+// artifacthub-repo.yml, served next to index.yaml. The same keys are used in the config.
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RepositoryMetadata {
+    #[serde(rename = "repositoryID", skip_serializing_if = "String::is_empty")]
+    pub repository_id: String, // Given by Artifact Hub when the repository is added to it.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub owners: Vec<Owner>, // Lets these users claim the repository.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ignore: Vec<Ignore>, // Charts, or versions of them, Artifact Hub should not index.
+}
+
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Owner {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Ignore {
+    pub name: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub version: String, // A regular expression; every version when empty.
+}
+
+impl RepositoryMetadata {
+    pub fn validate(&self) -> Result<(), String> {
+        let uuid = Regex::new(r"^[0-9a-fA-F]{8}(-[0-9a-fA-F]{4}){3}-[0-9a-fA-F]{12}$")
+            .map_err(|e| format!("error compiling regex: {e}"))?;
+        if !self.repository_id.is_empty() && !uuid.is_match(&self.repository_id) {
+            return Err(format!(
+                "Artifact Hub repositoryID `{}` is not a UUID",
+                self.repository_id
+            ));
+        }
+        if let Some(owner) = self.owners.iter().find(|o| !o.email.contains('@')) {
+            return Err(format!(
+                "Artifact Hub owner email `{}` is not an email address",
+                owner.email
+            ));
+        }
+        for ignore in &self.ignore {
+            if ignore.name.is_empty() {
+                return Err(String::from(
+                    "Artifact Hub ignore entries need a chart name",
+                ));
+            }
+            Regex::new(&ignore.version)
+                .map_err(|e| format!("Artifact Hub ignore version for {}: {e}", ignore.name))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Image {
+    name: String,
+    image: String,
+    #[serde(default, rename = "whitelisted")]
+    _whitelisted: bool,
+    #[serde(default, rename = "platforms")]
+    _platforms: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Link {
+    #[serde(rename = "name")]
+    _name: String,
+    url: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SignKey {
+    fingerprint: String,
+    url: String,
+}
+
+// One message per annotation that Artifact Hub would not be able to use.
+pub fn lint(annotations: &BTreeMap<String, String>) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (key, value) in annotations {
+        let Some(name) = key.strip_prefix(PREFIX) else {
+            continue;
+        };
+        let checked = match name {
+            "changes" => changelog::parse(value).map(|_| ()),
+            "containsSecurityUpdates" | "prerelease" => match value.trim() {
+                "true" | "false" => Ok(()),
+                _ => Err(String::from("must be `true` or `false`")),
+            },
+            "images" => parse::<Vec<Image>>(value).and_then(|images| {
+                match images.iter().find(|i| i.image.trim().is_empty()) {
+                    Some(image) => Err(format!("image {} has no reference", image.name)),
+                    None => Ok(()),
+                }
+            }),
+            "license" => check_license(value),
+            "links" => parse::<Vec<Link>>(value)
+                .and_then(|links| links.iter().try_for_each(|link| check_url(&link.url))),
+            "signKey" => parse::<SignKey>(value).and_then(|key| {
+                let hex = key.fingerprint.chars().all(|c| c.is_ascii_hexdigit());
+                if !hex || key.fingerprint.len() != 40 {
+                    return Err(format!(
+                        "fingerprint `{}` is not 40 hex digits",
+                        key.fingerprint
+                    ));
+                }
+                check_url(&key.url)
+            }),
+            name if KNOWN.contains(&name) => Ok(()),
+            _ => Err(String::from("is not an Artifact Hub annotation")),
+        };
+        if let Err(err) = checked {
+            problems.push(format!("annotation {key}: {err}"));
+        }
+    }
+    problems
+}
+
+fn parse<T: DeserializeOwned>(value: &str) -> Result<T, String> {
+    serde_yaml::from_str(value).map_err(|e| e.to_string())
+}
+
+fn check_url(url: &str) -> Result<(), String> {
+    match Url::parse(url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(()),
+        _ => Err(format!("`{url}` is not an http(s) URL")),
+    }
+}
+
+// SPDX identifiers, or expressions of them such as `MIT OR Apache-2.0`.
+fn check_license(license: &str) -> Result<(), String> {
+    let spdx =
+        Regex::new(r"^[A-Za-z0-9.+-]+$").map_err(|e| format!("error compiling regex: {e}"))?;
+    let terms = license.split([' ', '(', ')']).filter(|t| !t.is_empty());
+    let mut empty = true;
+    for term in terms {
+        empty = false;
+        if !matches!(term, "AND" | "OR" | "WITH") && !spdx.is_match(term) {
+            return Err(format!("`{license}` is not an SPDX license expression"));
+        }
+    }
+    if empty {
+        return Err(String::from("is empty"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::{lint, RepositoryMetadata};
+
+    #[test]
+    fn test_lint_artifacthub_annotations() {
+        let annotations: BTreeMap<String, String> = [
+            ("artifacthub.io/license", "MIT OR Apache-2.0"),
+            ("artifacthub.io/prerelease", "yes"),
+            ("artifacthub.io/containsSecurityUpdates", "true"),
+            (
+                "artifacthub.io/images",
+                "- name: app\n  image: ghcr.io/acme/app:1.0.0\n  platforms: [linux/amd64]\n",
+            ),
+            (
+                "artifacthub.io/links",
+                "- name: Docs\n  url: https://example.com\n- name: Chat\n  url: irc://example.com\n",
+            ),
+            (
+                "artifacthub.io/signKey",
+                "fingerprint: C874011F0AB405110D02105534365D9472D7468F\nurl: https://example.com/key.asc\n",
+            ),
+            ("artifacthub.io/category", "database"),
+            ("artifacthub.io/licence", "MIT"),
+            ("example.com/other", "anything"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+        assert_eq!(
+            lint(&annotations),
+            [
+                "annotation artifacthub.io/licence: is not an Artifact Hub annotation",
+                "annotation artifacthub.io/links: `irc://example.com` is not an http(s) URL",
+                "annotation artifacthub.io/prerelease: must be `true` or `false`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repository_metadata() {
+        let metadata: RepositoryMetadata = serde_yaml::from_str(
+            r#"
+            repositoryID: 5a64a4a2-7c4f-4d6e-9f2a-0c3b6f0f7e11
+            owners:
+              - name: Platform team
+                email: platform@example.com
+            ignore:
+              - name: internal-chart
+            "#,
+        )
+        .unwrap();
+        assert_eq!(metadata.validate(), Ok(()));
+        assert_eq!(
+            serde_yaml::to_string(&metadata).unwrap(),
+            "repositoryID: 5a64a4a2-7c4f-4d6e-9f2a-0c3b6f0f7e11\nowners:\n- name: Platform team\n  email: platform@example.com\nignore:\n- name: internal-chart\n"
+        );
+
+        let bad = RepositoryMetadata {
+            repository_id: String::from("not-a-uuid"),
+            ..Default::default()
+        };
+        assert!(bad.validate().is_err());
+    }
+}
@@ -0,0 +1,253 @@
+// Releases a chart: moves its version on in Chart.yaml, or in the override that sets it, and finds
+// the charts that depend on it.
+// Their constraints are only proposed unless asked to be written, since a dependent usually
+// wants a look before it takes a new major version. Every file is edited in place, so the
+// comments in them survive.
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use serde_yaml::Value;
+
+use super::constraint::Constraint;
+use super::document::Document;
+use super::merger;
+use super::spec::{Bump, Chart, Version};
+
+#[derive(Debug, Serialize)]
+pub struct BumpReport {
+    pub chart: String,
+    pub from: Version,
+    pub to: Version,
+    #[serde(rename = "appVersion", skip_serializing_if = "Option::is_none")]
+    pub app_version: Option<String>,
+    pub descriptor: PathBuf, // The layer the version was written to.
+    pub dependents: Vec<Dependent>,
+}
+
+// A chart whose dependencies reference the bumped chart.
+#[derive(Debug, Serialize)]
+pub struct Dependent {
+    pub chart: String,
+    pub descriptor: PathBuf,
+    pub constraint: String,
+    // What the constraint should become; `None` when it needs a person to rewrite it.
+    pub proposed: Option<String>,
+    pub updated: bool,
+}
+
+// `charts` are the charts that can be released, each with the Chart.yaml it is built from, and
+// `overrides` the folders layered on top of them.
+pub fn bump<'a>(
+    charts: impl IntoIterator<Item = (&'a Chart, &'a Path)> + Clone,
+    overrides: &[PathBuf],
+    name: &str,
+    part: Bump,
+    app_version: Option<&str>,
+    update_dependents: bool,
+) -> Result<BumpReport, Box<dyn Error>> {
+    let (chart, descriptor) = charts
+        .clone()
+        .into_iter()
+        .filter(|(chart, _)| chart.name == name)
+        .max_by(|(a, _), (b, _)| a.version.cmp(&b.version))
+        .ok_or_else(|| format!("{name} is not a chart from a chart root"))?;
+    let to = chart.version.bump(part)?;
+
+    // Later layers win, so each key is written where its value comes from.
+    let layers = merger::layer_paths(overrides, descriptor);
+    let version_layer = layer_setting(&layers, "version")?;
+    edit(&version_layer, |document| {
+        document.set(&["version"], Value::from(to.to_string()))
+    })?;
+    if let Some(app_version) = app_version {
+        edit(&layer_setting(&layers, "appVersion")?, |document| {
+            document.set(&["appVersion"], Value::from(app_version))
+        })?;
+    }
+
+    let mut dependents = Vec::new();
+    for (dependent, path) in charts {
+        for dependency in dependent.dependencies.iter().filter(|d| d.name == name) {
+            let proposed = dependency
+                .version
+                .parse::<Constraint>()
+                .ok()
+                .and_then(|constraint| constraint.retarget(&to));
+            let mut updated = false;
+            if let (true, Some(proposed)) = (update_dependents, &proposed) {
+                if *proposed != dependency.version {
+                    updated = set_constraint(path, name, proposed)?;
+                }
+            }
+            dependents.push(Dependent {
+                chart: dependent.name.clone(),
+                descriptor: path.to_path_buf(),
+                constraint: dependency.version.clone(),
+                proposed,
+                updated,
+            });
+        }
+    }
+
+    Ok(BumpReport {
+        chart: name.to_string(),
+        from: chart.version.clone(),
+        to,
+        app_version: app_version.map(str::to_string),
+        descriptor: version_layer,
+        dependents,
+    })
+}
+
+// Rewrites the constraint on `name` in a dependent's Chart.yaml. Dependencies that only come from
+// an override are left alone, and reported as not updated.
+fn set_constraint(path: &Path, name: &str, constraint: &str) -> Result<bool, Box<dyn Error>> {
+    let mut updated = false;
+    edit(path, |document| {
+        let Some(Value::Sequence(dependencies)) = document.get(&["dependencies"]) else {
+            return Ok(());
+        };
+        for (i, dependency) in dependencies.iter().enumerate() {
+            if dependency.get("name").and_then(Value::as_str) == Some(name) {
+                let index = i.to_string();
+                document.set(
+                    &["dependencies", &index, "version"],
+                    Value::from(constraint),
+                )?;
+                updated = true;
+            }
+        }
+        Ok(())
+    })?;
+    Ok(updated)
+}
+
+// The last layer that sets `key`, or the chart's own Chart.yaml when none does.
+fn layer_setting(layers: &[PathBuf], key: &str) -> Result<PathBuf, Box<dyn Error>> {
+    for layer in layers.iter().rev() {
+        let document = Document::parse(fs::read_to_string(layer)?)
+            .map_err(|e| format!("{}: {e}", layer.display()))?;
+        if document.get(&[key]).is_some() {
+            return Ok(layer.clone());
+        }
+    }
+    Ok(layers[0].clone())
+}
+
+fn edit(
+    path: &Path,
+    change: impl FnOnce(&mut Document) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let mut document = Document::parse(fs::read_to_string(path)?)
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+    change(&mut document)?;
+    fs::write(path, document.as_str())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::bump;
+    use crate::chart::merger;
+    use crate::chart::spec::{Bump, Chart};
+
+    #[test]
+    fn test_bump_rewrites_dependents_keeping_comments() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        fs::create_dir_all(&dir).unwrap();
+        let library = dir.join("library.yaml");
+        let app = dir.join("app.yaml");
+        let pinned = dir.join("pinned.yaml");
+        fs::write(
+            &library,
+            "apiVersion: v2\nname: library\nversion: 1.1.0 # released by CI\ntype: library\n",
+        )
+        .unwrap();
+        fs::write(
+            &app,
+            "apiVersion: v2\nname: app\nversion: 0.1.0\ndependencies:\n  # shared helpers\n  - name: library\n    version: \"~1.1.0\"\n",
+        )
+        .unwrap();
+        fs::write(
+            &pinned,
+            "apiVersion: v2\nname: pinned\nversion: 0.1.0\ndependencies:\n  - name: library\n    version: \">=1.0.0 <1.2.0\"\n",
+        )
+        .unwrap();
+
+        let charts: Vec<(Chart, _)> = [&library, &app, &pinned]
+            .into_iter()
+            .map(|path| {
+                let chart = serde_yaml::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+                (chart, path.as_path())
+            })
+            .collect();
+        let report = bump(
+            charts.iter().map(|(chart, path)| (chart, *path)),
+            &[],
+            "library",
+            Bump::Minor,
+            Some("2024.1"),
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(report.to.to_string(), "1.2.0");
+        assert_eq!(
+            fs::read_to_string(&library).unwrap(),
+            "apiVersion: v2\nname: library\nversion: 1.2.0 # released by CI\ntype: library\nappVersion: '2024.1'\n"
+        );
+        assert_eq!(
+            fs::read_to_string(&app).unwrap(),
+            "apiVersion: v2\nname: app\nversion: 0.1.0\ndependencies:\n  # shared helpers\n  - name: library\n    version: \"~1.2.0\"\n"
+        );
+        let outcomes: Vec<(&str, Option<&str>, bool)> = report
+            .dependents
+            .iter()
+            .map(|d| (d.chart.as_str(), d.proposed.as_deref(), d.updated))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![("app", Some("~1.2.0"), true), ("pinned", None, false)]
+        );
+    }
+    #[test]
+    fn test_bump_writes_the_layer_that_sets_the_version() {
+        let tmp = tempfile::tempdir().unwrap();
+        let base = tmp.path().join("charts/app/Chart.yaml");
+        let overrides = vec![tmp.path().join("local")];
+        let layer = merger::override_file(&overrides[0], &base);
+        fs::create_dir_all(base.parent().unwrap()).unwrap();
+        fs::create_dir_all(layer.parent().unwrap()).unwrap();
+        fs::write(&base, "apiVersion: v2\nname: app\nversion: 1.0.0\n").unwrap();
+        fs::write(&layer, "version: 1.0.5 # pinned for the release train\n").unwrap();
+
+        let chart: Chart =
+            serde_yaml::from_str("apiVersion: v2\nname: app\nversion: 1.0.5\n").unwrap();
+        let report = bump(
+            [(&chart, base.as_path())],
+            &overrides,
+            "app",
+            Bump::Patch,
+            Some("2.0"),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(report.to.to_string(), "1.0.6");
+        assert_eq!(report.descriptor, layer);
+        assert_eq!(
+            fs::read_to_string(&layer).unwrap(),
+            "version: 1.0.6 # pinned for the release train\n"
+        );
+        // No layer sets appVersion, so it goes to the chart's own Chart.yaml.
+        assert_eq!(
+            fs::read_to_string(&base).unwrap(),
+            "apiVersion: v2\nname: app\nversion: 1.0.0\nappVersion: '2.0'\n"
+        );
+    }
+}
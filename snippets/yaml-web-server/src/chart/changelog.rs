@@ -0,0 +1,225 @@
+// Changelogs from the `artifacthub.io/changes` annotation. Its value is itself YAML: a list of
+// plain descriptions, or of entries with a kind, a description and optional links.
+use std::fmt::{self, Write};
+
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+use super::spec::{RepositoryEntry, Version};
+
+pub const CHANGES_ANNOTATION: &str = "artifacthub.io/changes";
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Added,
+    Changed,
+    Deprecated,
+    Removed,
+    Fixed,
+    Security,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Change {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<Kind>, // Plain descriptions have none.
+    pub description: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<Link>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Link {
+    pub name: String,
+    pub url: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Release {
+    pub version: Version,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub created: String,
+    #[serde(rename = "appVersion", skip_serializing_if = "String::is_empty")]
+    pub app_version: String,
+    pub changes: Vec<Change>,
+}
+
+// Every version of a chart that lists its changes, newest first.
+#[derive(Debug, Serialize)]
+pub struct Changelog {
+    pub name: String,
+    pub releases: Vec<Release>,
+}
+
+pub fn parse(annotation: &str) -> Result<Vec<Change>, String> {
+    let items: Vec<Value> =
+        serde_yaml::from_str(annotation).map_err(|e| format!("is not a list: {e}"))?;
+    items
+        .into_iter()
+        .map(|item| match item {
+            Value::String(description) => Ok(Change {
+                kind: None,
+                description,
+                links: Vec::new(),
+            }),
+            item => serde_yaml::from_value(item).map_err(|e| format!("has an invalid entry: {e}")),
+        })
+        .collect()
+}
+
+impl Changelog {
+    // Versions without the annotation are left out; ones where it cannot be read are reported
+    // alongside, so a single bad release does not hide the rest.
+    pub fn new<'a>(
+        name: &str,
+        entries: impl IntoIterator<Item = &'a RepositoryEntry>,
+    ) -> (Changelog, Vec<String>) {
+        let mut releases = Vec::new();
+        let mut errors = Vec::new();
+        for entry in entries {
+            let Some(annotation) = entry.annotations.get(CHANGES_ANNOTATION) else {
+                continue;
+            };
+            match parse(annotation) {
+                Ok(changes) => releases.push(Release {
+                    version: entry.version.clone(),
+                    created: entry.created.clone(),
+                    app_version: entry.app_version.clone(),
+                    changes,
+                }),
+                Err(err) => errors.push(format!(
+                    "{name} {}: {CHANGES_ANNOTATION} {err}",
+                    entry.version
+                )),
+            }
+        }
+        releases.sort_by(|a, b| b.version.cmp(&a.version));
+
+        let changelog = Changelog {
+            name: name.to_string(),
+            releases,
+        };
+        (changelog, errors)
+    }
+}
+
+// Renders the changelog as Markdown, one section per release with its changes grouped by kind.
+impl fmt::Display for Changelog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "# {} changelog", self.name)?;
+        for release in &self.releases {
+            write!(f, "\n## {}", release.version)?;
+            if let Some(date) = release.created.get(..10) {
+                write!(f, " ({date})")?;
+            }
+            writeln!(f)?;
+
+            let mut kinds: Vec<Option<Kind>> = release.changes.iter().map(|c| c.kind).collect();
+            kinds.sort_by_key(|kind| (kind.is_none(), *kind));
+            kinds.dedup();
+            for kind in kinds {
+                let heading = match kind {
+                    Some(kind) => kind.to_string(),
+                    None => String::from("Other"),
+                };
+                writeln!(f, "\n### {heading}\n")?;
+                for change in release.changes.iter().filter(|c| c.kind == kind) {
+                    let mut line = format!("- {}", change.description.trim());
+                    for link in &change.links {
+                        write!(line, " ([{}]({}))", link.name, link.url)?;
+                    }
+                    writeln!(f, "{line}")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Kind::Added => "Added",
+            Kind::Changed => "Changed",
+            Kind::Deprecated => "Deprecated",
+            Kind::Removed => "Removed",
+            Kind::Fixed => "Fixed",
+            Kind::Security => "Security",
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Changelog, Kind, CHANGES_ANNOTATION};
+    use crate::chart::spec::RepositoryEntry;
+
+    #[test]
+    fn test_changelog_from_annotations() {
+        let entry = |version: &str, changes: Option<&str>| RepositoryEntry {
+            name: String::from("app"),
+            version: version.parse().unwrap(),
+            created: String::from("2024-03-01T10:00:00Z"),
+            annotations: changes
+                .map(|c| [(CHANGES_ANNOTATION.to_string(), c.to_string())].into())
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+        let entries = [
+            entry("1.0.0", Some("- First release\n")),
+            entry("1.1.0", None),
+            entry(
+                "1.2.0",
+                Some(
+                    r#"
+- kind: fixed
+  description: Probes use the right port
+  links:
+    - name: Issue 12
+      url: https://example.com/issues/12
+- kind: added
+  description: Ingress support
+"#,
+                ),
+            ),
+            entry("1.3.0", Some("- kind: broken\n  description: nope\n")),
+        ];
+
+        let (changelog, errors) = Changelog::new("app", &entries);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("app 1.3.0:"), "{}", errors[0]);
+
+        let versions: Vec<String> = changelog
+            .releases
+            .iter()
+            .map(|r| r.version.to_string())
+            .collect();
+        assert_eq!(versions, ["1.2.0", "1.0.0"]);
+        assert_eq!(changelog.releases[0].changes[0].kind, Some(Kind::Fixed));
+
+        assert_eq!(
+            changelog.to_string(),
+            r#"# app changelog
+
+## 1.2.0 (2024-03-01)
+
+### Added
+
+- Ingress support
+
+### Fixed
+
+- Probes use the right port ([Issue 12](https://example.com/issues/12))
+
+## 1.0.0 (2024-03-01)
+
+### Other
+
+- First release
+"#
+        );
+    }
+}
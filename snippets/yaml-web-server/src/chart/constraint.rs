@@ -0,0 +1,355 @@
+// Dependency version constraints the way Helm reads them (Masterminds semver): comparators joined
+// by commas or spaces must all hold, `||` separates alternatives, `1.2 - 1.4` is an inclusive
+// range, and `x`, `X` or `*` stand for any number. A prerelease only matches when one of the
+// comparators it is checked against names a prerelease itself.
+use std::fmt;
+use std::str::FromStr;
+
+use super::spec::Version;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constraint {
+    text: String,
+    alternatives: Vec<Vec<Comparator>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Comparator {
+    op: Op,
+    op_text: String,
+    parts: Vec<Option<i32>>, // Up to three numbers; `None` is a wildcard.
+    tokens: Vec<String>,     // The parts as written, so the shape survives a rewrite.
+    pre: String,
+    ceiling: Option<Version>, // The first version past the comparator's range, if it has one.
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Tilde,
+    Caret,
+}
+
+const OPERATORS: [(&str, Op); 12] = [
+    ("!=", Op::Ne),
+    (">=", Op::Ge),
+    ("=>", Op::Ge),
+    ("<=", Op::Le),
+    ("=<", Op::Le),
+    ("~>", Op::Tilde),
+    (">", Op::Gt),
+    ("<", Op::Lt),
+    ("=", Op::Eq),
+    ("~", Op::Tilde),
+    ("^", Op::Caret),
+    ("", Op::Eq),
+];
+
+impl FromStr for Constraint {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Constraint, String> {
+        let mut alternatives = Vec::new();
+        for alternative in text.split("||") {
+            let mut comparators = Vec::new();
+            let mut tokens = alternative
+                .split([',', ' '])
+                .filter(|t| !t.is_empty())
+                .peekable();
+
+            while let Some(token) = tokens.next() {
+                // `>= 1.2` puts the operator on its own.
+                let mut token = token.to_string();
+                if OPERATORS
+                    .iter()
+                    .any(|(op, _)| !op.is_empty() && *op == token)
+                {
+                    let version = tokens
+                        .next()
+                        .ok_or_else(|| format!("`{text}` ends with an operator"))?;
+                    token.push_str(version);
+                }
+                // `1.2 - 1.4` is `>=1.2 <=1.4`.
+                if tokens.peek() == Some(&"-") {
+                    tokens.next();
+                    let upper = tokens
+                        .next()
+                        .ok_or_else(|| format!("`{text}` has an open-ended range"))?;
+                    comparators.push(Comparator::parse(&format!(">={token}"))?);
+                    comparators.push(Comparator::parse(&format!("<={upper}"))?);
+                    continue;
+                }
+                comparators.push(Comparator::parse(&token)?);
+            }
+
+            if comparators.is_empty() {
+                comparators.push(Comparator::parse("*")?);
+            }
+            alternatives.push(comparators);
+        }
+
+        Ok(Constraint {
+            text: text.trim().to_string(),
+            alternatives,
+        })
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl Constraint {
+    pub fn matches(&self, version: &Version) -> bool {
+        self.alternatives.iter().any(|comparators| {
+            let prerelease_allowed = comparators.iter().any(|c| !c.pre.is_empty());
+            (version.slug.is_empty() || prerelease_allowed)
+                && comparators.iter().all(|c| c.matches(version))
+        })
+    }
+
+    pub fn newest<'a>(
+        &self,
+        versions: impl IntoIterator<Item = &'a Version>,
+    ) -> Option<&'a Version> {
+        versions.into_iter().filter(|v| self.matches(v)).max()
+    }
+
+    // The constraint moved on so that it takes `version`, keeping its operator and shape. It is
+    // returned as is when it already matches, and `None` means it is too involved to rewrite.
+    pub fn retarget(&self, version: &Version) -> Option<String> {
+        if self.matches(version) {
+            return Some(self.text.clone());
+        }
+        let [comparators] = self.alternatives.as_slice() else {
+            return None;
+        };
+        let [comparator] = comparators.as_slice() else {
+            return None;
+        };
+        if !matches!(comparator.op, Op::Eq | Op::Ge | Op::Tilde | Op::Caret) {
+            return None;
+        }
+
+        let numbers = [version.major, version.minor, version.bugfix];
+        let parts: Vec<String> = comparator
+            .parts
+            .iter()
+            .zip(&comparator.tokens)
+            .zip(numbers)
+            .map(|((part, token), number)| match part {
+                Some(_) => number.to_string(),
+                None => token.clone(),
+            })
+            .collect();
+        let mut rewritten = format!("{}{}", comparator.op_text, parts.join("."));
+        if parts.len() == 3 && !version.slug.is_empty() {
+            rewritten = format!("{rewritten}-{}", version.slug);
+        }
+        Some(rewritten)
+    }
+}
+
+impl Comparator {
+    fn parse(token: &str) -> Result<Comparator, String> {
+        let (op_text, op) = OPERATORS
+            .iter()
+            .find(|(op, _)| token.starts_with(op))
+            .map(|(text, op)| (text.to_string(), *op))
+            .unwrap_or((String::new(), Op::Eq));
+        let version = token[op_text.len()..].trim_start_matches('v');
+        let version = version.split('+').next().unwrap_or_default();
+        let (numbers, pre) = match version.split_once('-') {
+            Some((numbers, pre)) => (numbers, pre.to_string()),
+            None => (version, String::new()),
+        };
+
+        let mut parts = Vec::new();
+        let mut tokens = Vec::new();
+        for part in numbers.split('.').filter(|p| !p.is_empty()) {
+            parts.push(match part {
+                "x" | "X" | "*" => None,
+                number => Some(
+                    number
+                        .parse::<i32>()
+                        .map_err(|_| format!("`{token}` is not a version constraint"))?,
+                ),
+            });
+            tokens.push(part.to_string());
+        }
+        if parts.len() > 3 {
+            return Err(format!("`{token}` has too many version parts"));
+        }
+        // Anything after a wildcard is a wildcard too.
+        if let Some(first) = parts.iter().position(Option::is_none) {
+            parts[first..].iter_mut().for_each(|p| *p = None);
+        }
+
+        let mut comparator = Comparator {
+            op,
+            op_text,
+            parts,
+            tokens,
+            pre,
+            ceiling: None,
+        };
+        comparator.ceiling = comparator
+            .ceiling()
+            .ok_or_else(|| format!("`{token}` is out of range"))?;
+        Ok(comparator)
+    }
+
+    // How many leading numbers are given; the rest are wildcards.
+    fn precision(&self) -> usize {
+        self.parts.iter().take_while(|p| p.is_some()).count()
+    }
+
+    fn part(&self, i: usize) -> i32 {
+        self.parts.get(i).copied().flatten().unwrap_or(0)
+    }
+
+    fn floor(&self) -> Version {
+        Version {
+            major: self.part(0),
+            minor: self.part(1),
+            bugfix: self.part(2),
+            slug: self.pre.clone(),
+            build: String::new(),
+        }
+    }
+
+    // The first version past what a partial version such as `1.2` stands for, or past a `~` or
+    // `^` range. `None` when the ceiling does not fit in a version.
+    fn ceiling(&self) -> Option<Option<Version>> {
+        let (major, minor, bugfix) = (self.part(0), self.part(1), self.part(2));
+        let precision = self.precision();
+        let ceiling = match self.op {
+            _ if precision == 0 => None,
+            Op::Tilde if precision == 1 => Some(release(major.checked_add(1)?, 0, 0)),
+            Op::Tilde => Some(release(major, minor.checked_add(1)?, 0)),
+            Op::Caret if major > 0 || precision == 1 => Some(release(major.checked_add(1)?, 0, 0)),
+            Op::Caret if minor > 0 || precision == 2 => Some(release(0, minor.checked_add(1)?, 0)),
+            Op::Caret => Some(release(0, 0, bugfix.checked_add(1)?)),
+            _ if precision == 1 => Some(release(major.checked_add(1)?, 0, 0)),
+            _ if precision == 2 => Some(release(major, minor.checked_add(1)?, 0)),
+            _ => None,
+        };
+        Some(ceiling)
+    }
+
+    fn matches(&self, version: &Version) -> bool {
+        // Build metadata plays no part in matching: `1.2.3+up4.5.6` is `=1.2.3`.
+        let version = &Version {
+            build: String::new(),
+            ..version.clone()
+        };
+        let exact = self.precision() == 3;
+        let floor = self.floor();
+        let below_ceiling = |version: &Version| self.ceiling.as_ref().is_none_or(|c| version < c);
+
+        match self.op {
+            Op::Eq if exact => *version == floor,
+            Op::Eq => *version >= floor && below_ceiling(version),
+            Op::Ne if exact => *version != floor,
+            Op::Ne => !(*version >= floor && below_ceiling(version)),
+            Op::Gt if exact => *version > floor,
+            Op::Gt => self.ceiling.as_ref().is_some_and(|c| version >= c),
+            Op::Ge => *version >= floor,
+            Op::Lt => *version < floor,
+            Op::Le if exact => *version <= floor,
+            Op::Le => below_ceiling(version),
+            Op::Tilde | Op::Caret => *version >= floor && below_ceiling(version),
+        }
+    }
+}
+
+fn release(major: i32, minor: i32, bugfix: i32) -> Version {
+    Version {
+        major,
+        minor,
+        bugfix,
+        slug: String::new(),
+        build: String::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Constraint;
+    use crate::chart::spec::Version;
+
+    fn version(v: &str) -> Version {
+        v.parse().unwrap()
+    }
+
+    #[test]
+    fn test_constraints_match_like_helm() {
+        let cases = [
+            ("1.2.3", "1.2.3", true),
+            ("1.2.3", "1.2.4", false),
+            ("~1.2.3", "1.2.9", true),
+            ("~1.2.3", "1.3.0", false),
+            ("~1", "1.9.0", true),
+            ("^1.2.3", "1.9.0", true),
+            ("^1.2.3", "2.0.0", false),
+            ("^0.2.3", "0.2.9", true),
+            ("^0.2.3", "0.3.0", false),
+            ("1.2.x", "1.2.7", true),
+            ("1.x", "2.0.0", false),
+            ("*", "3.1.4", true),
+            (">=1.2 <2", "1.5.0", true),
+            (">= 1.2, < 2", "2.0.0", false),
+            ("1.2 - 1.4.5", "1.4.5", true),
+            ("1.2 - 1.4.5", "1.4.6", false),
+            ("<1.0.0 || >=3.0.0", "3.2.0", true),
+            ("<1.0.0 || >=3.0.0", "2.0.0", false),
+            ("^1.2.0", "1.3.0-rc.1", false),
+            (">=1.3.0-0", "1.3.0-rc.1", true),
+            ("!=1.2.3", "1.2.3", false),
+            ("1.2.3", "1.2.3+up4.5.6", true),
+            ("<=1.2.3", "1.2.3+up4.5.6", true),
+            ("<1.2.3", "1.2.3+up4.5.6", false),
+        ];
+
+        for (constraint, v, expected) in cases {
+            let parsed: Constraint = constraint.parse().unwrap();
+            assert_eq!(parsed.matches(&version(v)), expected, "{constraint} vs {v}");
+        }
+
+        // A range whose ceiling would not fit in a version is refused rather than overflowing.
+        let max = i32::MAX;
+        assert!(format!("^{max}.0.0").parse::<Constraint>().is_err());
+        assert!(format!("~1.{max}").parse::<Constraint>().is_err());
+        let exact: Constraint = format!("{max}.0.0").parse().unwrap();
+        assert!(exact.matches(&version(&format!("{max}.0.0"))));
+    }
+
+    #[test]
+    fn test_constraints_are_retargeted_in_their_own_shape() {
+        let cases = [
+            ("~1.1.0", "1.2.0", Some("~1.2.0")),
+            ("^1.1.0", "2.0.0", Some("^2.0.0")),
+            ("1.1.0", "1.1.1", Some("1.1.1")),
+            ("1.x", "2.0.0", Some("2.x")),
+            ("^1.1.0", "1.2.0", Some("^1.1.0")),
+            (">=1.0.0 <2.0.0", "2.0.0", None),
+            ("~1.1.0", "1.2.0-rc.0", Some("~1.2.0-rc.0")),
+        ];
+
+        for (constraint, v, expected) in cases {
+            let parsed: Constraint = constraint.parse().unwrap();
+            assert_eq!(
+                parsed.retarget(&version(v)).as_deref(),
+                expected,
+                "{constraint} to {v}"
+            );
+        }
+    }
+}
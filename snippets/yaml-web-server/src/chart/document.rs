@@ -0,0 +1,677 @@
+// Edits YAML text in place. Only the bytes of the value being changed are rewritten, so comments,
+// key order, blank lines and quoting everywhere else stay as they were. yaml-rust2's parser only
+// says where a node starts; where it ends is worked out from the source.
+use std::error::Error;
+use std::fmt;
+
+use serde_yaml::{Mapping, Value};
+use yaml_rust2::parser::{Event, Parser};
+use yaml_rust2::scanner::{Marker, TScalarStyle};
+
+pub struct Document {
+    text: String,
+}
+
+// A node of the parsed text; `start` and `end` are byte offsets.
+struct Node {
+    start: usize,
+    end: usize,
+    kind: Kind,
+}
+
+enum Kind {
+    Scalar {
+        value: String,
+        style: TScalarStyle,
+    },
+    Mapping {
+        flow: bool,
+        entries: Vec<(Node, Node)>,
+    },
+    Sequence {
+        flow: bool,
+        items: Vec<Node>,
+    },
+}
+
+// Where a node sits, which decides how a replacement has to be laid out.
+enum Slot {
+    Root,
+    Flow,
+    Value { colon: usize, indent: usize }, // A block mapping value; `indent` is its key's.
+    Item { dash: usize, indent: usize },   // A block sequence item; `indent` is its dash's.
+}
+
+impl Document {
+    pub fn parse(text: impl Into<String>) -> Result<Document, Box<dyn Error>> {
+        let document = Document { text: text.into() };
+        document.tree()?;
+        Ok(document)
+    }
+
+    pub fn get(&self, path: &[&str]) -> Option<Value> {
+        let mut value = serde_yaml::from_str::<Value>(&self.text).ok()?;
+        for key in path {
+            value = match value {
+                Value::Sequence(mut items) => {
+                    let index = key.parse::<usize>().ok()?;
+                    (index < items.len()).then(|| items.swap_remove(index))?
+                }
+                Value::Mapping(mut entries) => entries.remove(*key)?,
+                _ => return None,
+            };
+        }
+        Some(value)
+    }
+
+    // Sets the value at `path`, creating the mappings leading to it. Numeric segments index into
+    // sequences; the index right after the last item appends one.
+    pub fn set(&mut self, path: &[&str], value: Value) -> Result<(), Box<dyn Error>> {
+        let Some(root) = self.tree()? else {
+            self.append_to_root(path, value);
+            return Ok(());
+        };
+        let mut node = &root;
+        let mut slot = Slot::Root;
+
+        for (i, key) in path.iter().enumerate() {
+            match &node.kind {
+                Kind::Mapping { flow, entries } => {
+                    let found = entries.iter().find(|(k, _)| k.scalar() == Some(*key));
+                    let Some((k, v)) = found else {
+                        self.insert_entry(node, *flow, entries, &path[i..], value);
+                        return Ok(());
+                    };
+                    slot = match flow {
+                        true => Slot::Flow,
+                        false => Slot::Value {
+                            colon: self.colon_after(k.end),
+                            indent: self.col(k.start),
+                        },
+                    };
+                    node = v;
+                }
+                Kind::Sequence { flow, items } => {
+                    let index: usize = key
+                        .parse()
+                        .map_err(|_| format!("`{key}` does not index a sequence"))?;
+                    if index == items.len() {
+                        self.append_item(node, *flow, items, nest(&path[i + 1..], value));
+                        return Ok(());
+                    }
+                    let item = items
+                        .get(index)
+                        .ok_or_else(|| format!("index {index} is out of range"))?;
+                    slot = match flow {
+                        true => Slot::Flow,
+                        false => {
+                            let dash = self.dash_before(item.start);
+                            Slot::Item {
+                                dash,
+                                indent: self.col(dash),
+                            }
+                        }
+                    };
+                    node = item;
+                }
+                Kind::Scalar { .. } => {
+                    self.replace(node, &slot, nest(&path[i..], value));
+                    return Ok(());
+                }
+            }
+        }
+
+        self.replace(node, &slot, value);
+        Ok(())
+    }
+
+    // Applies a partial document: every leaf of `patch` is set, nested mappings are walked into.
+    // An empty patch sets nothing, rather than replacing the whole document with `{}`.
+    pub fn merge(&mut self, patch: &Value) -> Result<(), Box<dyn Error>> {
+        if !patch.is_mapping() {
+            return Err("a patch must be a mapping".into());
+        }
+        let mut leaves = Vec::new();
+        collect_leaves(patch, &mut Vec::new(), &mut leaves)?;
+        for (path, value) in leaves {
+            let path: Vec<&str> = path.iter().map(String::as_str).collect();
+            self.set(&path, value)?;
+        }
+        Ok(())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    fn replace(&mut self, node: &Node, slot: &Slot, value: Value) {
+        if let Kind::Scalar { style, .. } = &node.kind {
+            if let Some(scalar) = render_scalar(&value, *style) {
+                self.text.replace_range(node.start..node.end, &scalar);
+                return;
+            }
+        }
+
+        match *slot {
+            Slot::Root => self.text = format!("{}\n", render(&value)),
+            Slot::Flow => self
+                .text
+                .replace_range(node.start..node.end, &render_flow(&value)),
+            Slot::Value { colon, indent } => self
+                .text
+                .replace_range(colon + 1..node.end, &block_value(&value, indent)),
+            Slot::Item { dash, indent } => self.text.replace_range(
+                dash + 1..node.end,
+                &format!(" {}", indent_rest(&render(&value), indent + 2)),
+            ),
+        }
+    }
+
+    fn insert_entry(
+        &mut self,
+        mapping: &Node,
+        flow: bool,
+        entries: &[(Node, Node)],
+        path: &[&str],
+        value: Value,
+    ) {
+        let key = render(&Value::from(path[0]));
+        let value = nest(&path[1..], value);
+
+        if flow {
+            let separator = if entries.is_empty() { "" } else { ", " };
+            let entry = format!("{separator}{key}: {}", render_flow(&value));
+            self.text.insert_str(mapping.end - 1, &entry);
+        } else if let (Some((first, _)), Some((_, last))) = (entries.first(), entries.last()) {
+            let indent = self.col(first.start);
+            let entry = format!(
+                "\n{}{key}:{}",
+                " ".repeat(indent),
+                block_value(&value, indent)
+            );
+            self.text.insert_str(self.line_end(last.end), &entry);
+        }
+    }
+
+    fn append_item(&mut self, sequence: &Node, flow: bool, items: &[Node], value: Value) {
+        if flow {
+            let separator = if items.is_empty() { "" } else { ", " };
+            let item = format!("{separator}{}", render_flow(&value));
+            self.text.insert_str(sequence.end - 1, &item);
+        } else if let (Some(first), Some(last)) = (items.first(), items.last()) {
+            let indent = self.col(self.dash_before(first.start));
+            let item = format!(
+                "\n{}- {}",
+                " ".repeat(indent),
+                indent_rest(&render(&value), indent + 2)
+            );
+            self.text.insert_str(self.line_end(last.end), &item);
+        }
+    }
+
+    // A document with nothing but comments in it.
+    fn append_to_root(&mut self, path: &[&str], value: Value) {
+        if !self.text.is_empty() && !self.text.ends_with('\n') {
+            self.text.push('\n');
+        }
+        let value = nest(path, value);
+        self.text.push_str(&render(&value));
+        self.text.push('\n');
+    }
+
+    fn tree(&self) -> Result<Option<Node>, Box<dyn Error>> {
+        let mut parser = Parser::new_from_str(&self.text);
+        let mut events = Vec::new();
+        loop {
+            let (event, marker) = parser.next_token()?;
+            if event == Event::StreamEnd {
+                break;
+            }
+            events.push((event, marker));
+        }
+
+        let offsets: Vec<usize> = self
+            .text
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain([self.text.len()])
+            .collect();
+        let mut builder = Builder {
+            document: self,
+            offsets,
+            events: events.into_iter(),
+        };
+
+        loop {
+            match builder.events.next() {
+                Some((Event::DocumentStart, _)) => break,
+                Some(_) => continue,
+                None => return Ok(None),
+            }
+        }
+        match builder.node()? {
+            Node {
+                kind: Kind::Scalar { value, style },
+                ..
+            } if value.is_empty() && style == TScalarStyle::Plain => Ok(None),
+            node => Ok(Some(node)),
+        }
+    }
+
+    fn col(&self, offset: usize) -> usize {
+        offset - self.text[..offset].rfind('\n').map_or(0, |i| i + 1)
+    }
+
+    fn line_end(&self, offset: usize) -> usize {
+        self.text[offset..]
+            .find('\n')
+            .map_or(self.text.len(), |i| offset + i)
+    }
+
+    fn colon_after(&self, offset: usize) -> usize {
+        let rest = &self.text[offset..];
+        let skipped = rest.len() - rest.trim_start_matches([' ', '\t']).len();
+        match rest[skipped..].starts_with(':') {
+            true => offset + skipped,
+            false => offset,
+        }
+    }
+
+    fn dash_before(&self, offset: usize) -> usize {
+        self.text[..offset].rfind('-').unwrap_or(offset)
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl Node {
+    fn scalar(&self) -> Option<&str> {
+        match &self.kind {
+            Kind::Scalar { value, .. } => Some(value),
+            _ => None,
+        }
+    }
+}
+
+struct Builder<'a> {
+    document: &'a Document,
+    offsets: Vec<usize>, // Byte offset of every char; markers count chars.
+    events: std::vec::IntoIter<(Event, Marker)>,
+}
+
+impl Builder<'_> {
+    fn node(&mut self) -> Result<Node, Box<dyn Error>> {
+        let (event, marker) = self.events.next().ok_or("unexpected end of document")?;
+        let start = self.offset(&marker);
+        let text = &self.document.text;
+
+        match event {
+            Event::Scalar(value, style, ..) => {
+                let (start, end) = match style {
+                    TScalarStyle::Plain => (start, plain_end(text, start, &value)),
+                    TScalarStyle::SingleQuoted => (start, quoted_end(text, start, '\'')),
+                    TScalarStyle::DoubleQuoted => (start, quoted_end(text, start, '"')),
+                    TScalarStyle::Literal | TScalarStyle::Folded => block_span(text, start),
+                };
+                Ok(Node {
+                    start,
+                    end,
+                    kind: Kind::Scalar { value, style },
+                })
+            }
+            Event::Alias(_) => Ok(Node {
+                start,
+                end: plain_end(text, start, ""),
+                kind: Kind::Scalar {
+                    value: String::new(),
+                    style: TScalarStyle::Plain,
+                },
+            }),
+            Event::SequenceStart(..) => {
+                let mut items: Vec<Node> = Vec::new();
+                loop {
+                    if let Some(end) = self.end_of(&Event::SequenceEnd) {
+                        let flow = text[start..].starts_with('[') && text[end..].starts_with(']');
+                        let (start, end) = match (flow, items.first(), items.last()) {
+                            (true, _, _) => (start, end + 1),
+                            (false, Some(first), Some(last)) => {
+                                (self.document.dash_before(first.start), last.end)
+                            }
+                            _ => (start, start),
+                        };
+                        return Ok(Node {
+                            start,
+                            end,
+                            kind: Kind::Sequence { flow, items },
+                        });
+                    }
+                    let mut item = self.node()?;
+                    if item.scalar() == Some("") && item.start > 0 {
+                        // An empty item; point it right after its dash.
+                        let dash = self.document.dash_before(item.start);
+                        if text[dash + 1..item.start].trim().is_empty() {
+                            (item.start, item.end) = (dash + 1, dash + 1);
+                        }
+                    }
+                    items.push(item);
+                }
+            }
+            Event::MappingStart(..) => {
+                let mut entries: Vec<(Node, Node)> = Vec::new();
+                loop {
+                    if let Some(end) = self.end_of(&Event::MappingEnd) {
+                        let flow = text[start..].starts_with('{') && text[end..].starts_with('}');
+                        let (start, end) = match (flow, entries.first(), entries.last()) {
+                            (true, _, _) => (start, end + 1),
+                            (false, Some((key, _)), Some((_, value))) => (key.start, value.end),
+                            _ => (start, start),
+                        };
+                        return Ok(Node {
+                            start,
+                            end,
+                            kind: Kind::Mapping { flow, entries },
+                        });
+                    }
+                    let key = self.node()?;
+                    let mut value = self.node()?;
+                    if let Kind::Scalar {
+                        value: scalar,
+                        style: TScalarStyle::Plain,
+                    } = &value.kind
+                    {
+                        // A missing value is reported where the next token starts.
+                        let colon = self.document.colon_after(key.end);
+                        if scalar.is_empty() && value.start > colon {
+                            (value.start, value.end) = (colon + 1, colon + 1);
+                        }
+                    }
+                    entries.push((key, value));
+                }
+            }
+            event => Err(format!("unexpected {event:?}").into()),
+        }
+    }
+
+    // Consumes the closing event if it is next, returning where it is.
+    fn end_of(&mut self, closing: &Event) -> Option<usize> {
+        match self.events.as_slice().first() {
+            Some((event, marker)) if event == closing => {
+                let offset = self.offset(marker);
+                self.events.next();
+                Some(offset)
+            }
+            _ => None,
+        }
+    }
+
+    fn offset(&self, marker: &Marker) -> usize {
+        self.offsets[marker.index().min(self.offsets.len() - 1)]
+    }
+}
+
+// Matches the parsed value against the source; line breaks of a multi-line plain scalar are
+// folded into the value, so whitespace only has to line up loosely.
+fn plain_end(text: &str, start: usize, value: &str) -> usize {
+    let source = &text[start..];
+    if value.is_empty() {
+        let len = source
+            .find(|c: char| c.is_whitespace() || ",]}".contains(c))
+            .unwrap_or(source.len());
+        return start + len;
+    }
+
+    let mut wanted = value.chars().peekable();
+    let mut end = 0;
+    let mut chars = source.char_indices().peekable();
+    while let Some(&want) = wanted.peek() {
+        let Some(&(i, c)) = chars.peek() else {
+            break;
+        };
+        if c == want {
+            wanted.next();
+            chars.next();
+            end = i + c.len_utf8();
+        } else if c.is_whitespace() {
+            while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+            while wanted.next_if(|c| c.is_whitespace()).is_some() {}
+        } else {
+            break;
+        }
+    }
+    start + end
+}
+
+fn quoted_end(text: &str, start: usize, quote: char) -> usize {
+    let mut chars = text[start..].char_indices().skip(1).peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if quote == '"' => {
+                chars.next();
+            }
+            '\'' if quote == '\'' && chars.peek().map(|(_, c)| *c) == Some('\'') => {
+                chars.next();
+            }
+            c if c == quote => return start + i + 1,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+// Block scalars are reported where their content starts; the span reaches back to the `|` or
+// `>` header and down to the last line indented at least as far as the content.
+fn block_span(text: &str, content: usize) -> (usize, usize) {
+    let header = text[..content].rfind(['|', '>']).unwrap_or(content);
+    let line_start = text[..content].rfind('\n').map_or(0, |i| i + 1);
+    let indent = content - line_start;
+
+    let mut end = text[content..]
+        .find('\n')
+        .map_or(text.len(), |i| content + i);
+    let mut line = end + 1;
+    while line < text.len() {
+        let line_end = text[line..].find('\n').map_or(text.len(), |i| line + i);
+        let content = &text[line..line_end];
+        if !content.trim().is_empty() {
+            if content.len() - content.trim_start().len() < indent {
+                break;
+            }
+            end = line_end;
+        }
+        line = line_end + 1;
+    }
+    (header, end)
+}
+
+fn nest(path: &[&str], value: Value) -> Value {
+    path.iter().rev().fold(value, |value, key| {
+        let mut mapping = Mapping::new();
+        mapping.insert(Value::from(*key), value);
+        Value::Mapping(mapping)
+    })
+}
+
+fn collect_leaves(
+    patch: &Value,
+    path: &mut Vec<String>,
+    leaves: &mut Vec<(Vec<String>, Value)>,
+) -> Result<(), Box<dyn Error>> {
+    match patch {
+        // Below the top, an empty mapping is a value to set like any other.
+        Value::Mapping(mapping) if path.is_empty() || !mapping.is_empty() => {
+            for (key, value) in mapping {
+                let key = key.as_str().ok_or("patch keys must be strings")?;
+                path.push(key.to_string());
+                collect_leaves(value, path, leaves)?;
+                path.pop();
+            }
+        }
+        value => leaves.push((path.clone(), value.clone())),
+    }
+    Ok(())
+}
+
+fn render(value: &Value) -> String {
+    serde_yaml::to_string(value)
+        .unwrap_or_default()
+        .trim_end_matches('\n')
+        .to_string()
+}
+
+// JSON is valid flow YAML.
+fn render_flow(value: &Value) -> String {
+    serde_json::to_string(value).unwrap_or_else(|_| render(value))
+}
+
+// A one-line scalar written the way the old one was quoted; `None` when it cannot be.
+fn render_scalar(value: &Value, style: TScalarStyle) -> Option<String> {
+    let scalar = match (value, style) {
+        (Value::Mapping(_) | Value::Sequence(_) | Value::Tagged(_), _) => return None,
+        (_, TScalarStyle::Literal | TScalarStyle::Folded) => return None,
+        (Value::String(s), TScalarStyle::SingleQuoted) => format!("'{}'", s.replace('\'', "''")),
+        (Value::String(s), TScalarStyle::DoubleQuoted) => serde_json::to_string(s).ok()?,
+        (value, _) => render(value),
+    };
+    (!scalar.contains('\n')).then_some(scalar)
+}
+
+// What follows `key:` in a block mapping whose keys are at `indent`.
+fn block_value(value: &Value, indent: usize) -> String {
+    let nested = match value {
+        Value::Mapping(m) => !m.is_empty(),
+        Value::Sequence(s) => !s.is_empty(),
+        _ => false,
+    };
+    let rendered = render(value);
+    match nested {
+        true => format!(
+            "\n{}{}",
+            " ".repeat(indent + 2),
+            indent_rest(&rendered, indent + 2)
+        ),
+        false => format!(" {}", indent_rest(&rendered, indent)),
+    }
+}
+
+fn indent_rest(text: &str, indent: usize) -> String {
+    let padding = " ".repeat(indent);
+    let mut lines = text.lines();
+    let mut indented = lines.next().unwrap_or_default().to_string();
+    for line in lines {
+        indented.push('\n');
+        if !line.is_empty() {
+            indented.push_str(&padding);
+        }
+        indented.push_str(line);
+    }
+    indented
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use super::Document;
+
+    #[test]
+    fn test_edits_keep_comments_and_quoting() {
+        let chart_yaml = include_str!("../../charts/test-chart-1/Chart.yaml");
+        let mut chart = Document::parse(chart_yaml).unwrap();
+        chart.set(&["version"], Value::from("0.2.0")).unwrap();
+        chart.set(&["appVersion"], Value::from("1.17.0")).unwrap();
+
+        let expected = chart_yaml
+            .replace("version: 0.1.0-slug", "version: 0.2.0")
+            .replace("appVersion: \"1.16.0\"", "appVersion: \"1.17.0\"");
+        assert_eq!(chart.as_str(), expected);
+
+        let values_yaml = include_str!("../../charts/test-chart-1/values.yaml");
+        let mut values = Document::parse(values_yaml).unwrap();
+        values.set(&["image", "tag"], Value::from("1.25")).unwrap();
+        values
+            .set(&["image", "digest"], Value::from("sha256:abc"))
+            .unwrap();
+        values
+            .set(
+                &["serviceAccount", "annotations", "team"],
+                Value::from("web"),
+            )
+            .unwrap();
+        values.set(&["replicaCount"], Value::from(3)).unwrap();
+
+        let text = values.as_str();
+        assert!(text.contains(
+            "  # Overrides the image tag whose default is the chart appVersion.\n  tag: \"1.25\"\n  digest: sha256:abc\n"
+        ));
+        assert!(text.contains("  annotations: {team: \"web\"}\n"));
+        assert!(text.contains("replicaCount: 3\n"));
+        assert_eq!(
+            text.lines()
+                .filter(|l| l.trim_start().starts_with('#'))
+                .count(),
+            values_yaml
+                .lines()
+                .filter(|l| l.trim_start().starts_with('#'))
+                .count()
+        );
+        assert_eq!(
+            values.get(&["image", "pullPolicy"]),
+            Some(Value::from("IfNotPresent"))
+        );
+
+        let before = values.as_str().to_string();
+        values.merge(&serde_yaml::from_str("{}").unwrap()).unwrap();
+        assert_eq!(values.as_str(), before);
+        assert!(values.merge(&Value::from("replaced")).is_err());
+    }
+
+    #[test]
+    fn test_edits_sequences_and_nested_values() {
+        let mut document = Document::parse(
+            "# Dependencies\ndependencies:\n- name: common # shared helpers\n  version: '1.0.0'\nconfig:\n",
+        )
+        .unwrap();
+        document
+            .set(&["dependencies", "0", "version"], Value::from("1.1.0"))
+            .unwrap();
+        document
+            .set(
+                &["dependencies", "1"],
+                serde_yaml::from_str("{name: redis, version: 17.0.0}").unwrap(),
+            )
+            .unwrap();
+        document
+            .set(&["config", "log", "level"], Value::from("debug"))
+            .unwrap();
+
+        assert_eq!(
+            document.as_str(),
+            "# Dependencies\ndependencies:\n- name: common # shared helpers\n  version: '1.1.0'\n- name: redis\n  version: 17.0.0\nconfig:\n  log:\n    level: debug\n"
+        );
+
+        let mut blocks = Document::parse(
+            "notes: |\n  first\n  second\n\n# kept\ntags: [a, b] # flow\nafter: 1\n",
+        )
+        .unwrap();
+        blocks.set(&["notes"], Value::from("one line")).unwrap();
+        blocks.set(&["tags", "2"], Value::from("c")).unwrap();
+        blocks.set(&["tags", "0"], Value::from("z")).unwrap();
+        assert_eq!(
+            blocks.as_str(),
+            "notes: one line\n\n# kept\ntags: [z, b, \"c\"] # flow\nafter: 1\n"
+        );
+
+        let mut empty = Document::parse("# Overrides for the chart\n").unwrap();
+        empty
+            .merge(&serde_yaml::from_str("{image: {tag: v2}, replicas: 2}").unwrap())
+            .unwrap();
+        assert_eq!(
+            empty.as_str(),
+            "# Overrides for the chart\nimage:\n  tag: v2\nreplicas: 2\n"
+        );
+    }
+}
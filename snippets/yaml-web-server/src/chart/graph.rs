@@ -0,0 +1,421 @@
+// The dependency graph of a repository: one node per chart, drawn from its newest served version,
+// and one edge per dependency. Dependencies on charts the repository does not serve are kept as
+// nodes of their own, so the picture shows everything a chart pulls in.
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::Write;
+
+use serde::Serialize;
+
+use super::constraint::Constraint;
+use super::spec::{Repository, RepositoryEntry, Version};
+
+#[derive(Debug, Serialize)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+    // Groups of charts that depend on each other, directly or not.
+    pub cycles: Vec<Vec<String>>,
+    // Library charts no chart depends on; nothing can install them.
+    #[serde(rename = "unreachableLibraries")]
+    pub unreachable_libraries: Vec<String>,
+    pub conflicts: Vec<DiamondConflict>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Node {
+    pub name: String,
+    pub version: Option<Version>, // `None` when the repository does not serve the chart.
+    pub library: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    pub constraint: String,
+    pub resolved: Option<Version>, // The newest served version the constraint takes.
+}
+
+// A chart needed more than once below `root`, under constraints no served version meets at once.
+#[derive(Debug, Serialize)]
+pub struct DiamondConflict {
+    pub root: String,
+    pub chart: String,
+    pub requirements: Vec<Requirement>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Requirement {
+    pub by: String,
+    pub constraint: String,
+}
+
+impl Graph {
+    pub fn new(index: &Repository) -> Graph {
+        let latest: BTreeMap<&str, &RepositoryEntry> = index
+            .entries
+            .iter()
+            .filter_map(|(name, entries)| {
+                let entry = entries.iter().max_by(|a, b| a.version.cmp(&b.version))?;
+                Some((name.as_str(), entry))
+            })
+            .collect();
+
+        let mut edges = Vec::new();
+        for (name, entry) in &latest {
+            for dependency in &entry.dependencies {
+                let resolved =
+                    dependency
+                        .version
+                        .parse::<Constraint>()
+                        .ok()
+                        .and_then(|constraint| {
+                            let entries = index.entries.get(&dependency.name)?;
+                            constraint
+                                .newest(entries.iter().map(|e| &e.version))
+                                .cloned()
+                        });
+                edges.push(Edge {
+                    from: name.to_string(),
+                    to: dependency.name.clone(),
+                    constraint: dependency.version.clone(),
+                    resolved,
+                });
+            }
+        }
+
+        let names: BTreeSet<&str> = latest
+            .keys()
+            .copied()
+            .chain(edges.iter().map(|e| e.to.as_str()))
+            .collect();
+        let nodes: Vec<Node> = names
+            .into_iter()
+            .map(|name| Node {
+                name: name.to_string(),
+                version: latest.get(name).map(|e| e.version.clone()),
+                library: latest.get(name).is_some_and(|e| e.type_ == "library"),
+            })
+            .collect();
+
+        let unreachable_libraries = nodes
+            .iter()
+            .filter(|node| node.library && !edges.iter().any(|e| e.to == node.name))
+            .map(|node| node.name.clone())
+            .collect();
+
+        let mut graph = Graph {
+            nodes,
+            edges,
+            cycles: Vec::new(),
+            unreachable_libraries,
+            conflicts: Vec::new(),
+        };
+        graph.cycles = graph.cycles();
+        graph.conflicts = graph.conflicts(index);
+        graph
+    }
+
+    fn dependencies<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Edge> {
+        self.edges.iter().filter(move |e| e.from == name)
+    }
+
+    // Tarjan's strongly connected components; a component is a cycle when it has more than one
+    // chart or a chart that depends on itself.
+    fn cycles(&self) -> Vec<Vec<String>> {
+        struct Search<'a> {
+            graph: &'a Graph,
+            next: usize,
+            index: BTreeMap<&'a str, usize>,
+            low: BTreeMap<&'a str, usize>,
+            stack: Vec<&'a str>,
+            cycles: Vec<Vec<String>>,
+        }
+
+        impl<'a> Search<'a> {
+            fn visit(&mut self, name: &'a str) {
+                self.index.insert(name, self.next);
+                self.low.insert(name, self.next);
+                self.next += 1;
+                self.stack.push(name);
+
+                for edge in self.graph.dependencies(name) {
+                    let to = edge.to.as_str();
+                    if !self.index.contains_key(to) {
+                        self.visit(to);
+                        let low = self.low[name].min(self.low[to]);
+                        self.low.insert(name, low);
+                    } else if self.stack.contains(&to) {
+                        let low = self.low[name].min(self.index[to]);
+                        self.low.insert(name, low);
+                    }
+                }
+
+                if self.low[name] == self.index[name] {
+                    let start = self.stack.iter().rposition(|n| *n == name).unwrap_or(0);
+                    let mut component: Vec<String> =
+                        self.stack.drain(start..).map(str::to_string).collect();
+                    let looped = self.graph.dependencies(name).any(|e| e.to == name);
+                    if component.len() > 1 || looped {
+                        component.sort();
+                        self.cycles.push(component);
+                    }
+                }
+            }
+        }
+
+        let mut search = Search {
+            graph: self,
+            next: 0,
+            index: BTreeMap::new(),
+            low: BTreeMap::new(),
+            stack: Vec::new(),
+            cycles: Vec::new(),
+        };
+        for node in &self.nodes {
+            if !search.index.contains_key(node.name.as_str()) {
+                search.visit(&node.name);
+            }
+        }
+        let mut cycles = search.cycles;
+        cycles.sort();
+        cycles
+    }
+
+    // Walks down from every chart nothing depends on and gathers what each chart below it is
+    // asked for. Constraints that cannot be parsed are left to Helm to complain about.
+    fn conflicts(&self, index: &Repository) -> Vec<DiamondConflict> {
+        let mut conflicts = Vec::new();
+        let roots = self
+            .nodes
+            .iter()
+            .filter(|node| !self.edges.iter().any(|e| e.to == node.name));
+
+        for root in roots {
+            let mut requirements: BTreeMap<&str, Vec<Requirement>> = BTreeMap::new();
+            let mut seen = BTreeSet::from([root.name.as_str()]);
+            let mut queue = VecDeque::from([root.name.as_str()]);
+            while let Some(name) = queue.pop_front() {
+                for edge in self.dependencies(name) {
+                    let requirement = Requirement {
+                        by: edge.from.clone(),
+                        constraint: edge.constraint.clone(),
+                    };
+                    let required = requirements.entry(&edge.to).or_default();
+                    if !required.contains(&requirement) {
+                        required.push(requirement);
+                    }
+                    if seen.insert(&edge.to) {
+                        queue.push_back(&edge.to);
+                    }
+                }
+            }
+
+            for (chart, requirements) in requirements {
+                let Some(entries) = index.entries.get(chart) else {
+                    continue;
+                };
+                let Ok(constraints) = requirements
+                    .iter()
+                    .map(|r| r.constraint.parse::<Constraint>())
+                    .collect::<Result<Vec<_>, _>>()
+                else {
+                    continue;
+                };
+                let satisfiable = entries
+                    .iter()
+                    .any(|e| constraints.iter().all(|c| c.matches(&e.version)));
+                if requirements.len() > 1 && !satisfiable {
+                    conflicts.push(DiamondConflict {
+                        root: root.name.clone(),
+                        chart: chart.to_string(),
+                        requirements,
+                    });
+                }
+            }
+        }
+        conflicts
+    }
+
+    fn in_cycle(&self, edge: &Edge) -> bool {
+        self.cycles
+            .iter()
+            .any(|cycle| cycle.contains(&edge.from) && cycle.contains(&edge.to))
+    }
+
+    // Library charts are boxes, charts the repository does not serve are dashed and the edges of
+    // a cycle are red.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph charts {\n  rankdir=LR;\n");
+        for node in &self.nodes {
+            let mut attributes = vec![format!("label={:?}", node.label())];
+            if node.library {
+                attributes.push(String::from("shape=box"));
+            }
+            if node.version.is_none() {
+                attributes.push(String::from("style=dashed"));
+            }
+            let _ = writeln!(dot, "  {:?} [{}];", node.name, attributes.join(", "));
+        }
+        for edge in &self.edges {
+            let mut attributes = vec![format!("label={:?}", edge.constraint)];
+            if self.in_cycle(edge) {
+                attributes.push(String::from("color=red"));
+            }
+            let _ = writeln!(
+                dot,
+                "  {:?} -> {:?} [{}];",
+                edge.from,
+                edge.to,
+                attributes.join(", ")
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    // The same picture as `to_dot`. Node ids are positions, since chart names may hold characters
+    // Mermaid reads as syntax.
+    pub fn to_mermaid(&self) -> String {
+        let id = |name: &str| self.nodes.iter().position(|n| n.name == name).unwrap_or(0);
+        let mut mermaid = String::from("graph LR\n");
+        for (i, node) in self.nodes.iter().enumerate() {
+            let label = mermaid_text(&node.label());
+            let _ = if node.library {
+                writeln!(mermaid, "  n{i}[[\"{label}\"]]")
+            } else {
+                writeln!(mermaid, "  n{i}[\"{label}\"]")
+            };
+        }
+        for (i, edge) in self.edges.iter().enumerate() {
+            let _ = writeln!(
+                mermaid,
+                "  n{} -->|\"{}\"| n{}",
+                id(&edge.from),
+                mermaid_text(&edge.constraint),
+                id(&edge.to)
+            );
+            if self.in_cycle(edge) {
+                let _ = writeln!(mermaid, "  linkStyle {i} stroke:red");
+            }
+        }
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.version.is_none() {
+                let _ = writeln!(mermaid, "  style n{i} stroke-dasharray: 5 5");
+            }
+        }
+        mermaid
+    }
+}
+
+impl Node {
+    fn label(&self) -> String {
+        match &self.version {
+            Some(version) => format!("{} {version}", self.name),
+            None => self.name.clone(),
+        }
+    }
+}
+
+fn mermaid_text(text: &str) -> String {
+    text.replace('"', "#quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Graph, Requirement};
+    use crate::chart::spec::{Dependency, Repository, RepositoryEntry};
+
+    fn entry(
+        name: &str,
+        version: &str,
+        library: bool,
+        dependencies: &[(&str, &str)],
+    ) -> RepositoryEntry {
+        RepositoryEntry {
+            name: name.to_string(),
+            version: version.parse().unwrap(),
+            type_: String::from(if library { "library" } else { "application" }),
+            dependencies: dependencies
+                .iter()
+                .map(|(name, version)| Dependency {
+                    name: name.to_string(),
+                    version: version.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_graph_analysis_and_export() {
+        let mut index = Repository::new();
+        for entry in [
+            entry(
+                "app",
+                "1.0.0",
+                false,
+                &[("api", "^1.0.0"), ("web", "^1.0.0")],
+            ),
+            entry("api", "1.0.0", false, &[("common", "~1.1.0")]),
+            entry(
+                "web",
+                "1.0.0",
+                false,
+                &[("common", "~1.2.0"), ("redis", "17.x")],
+            ),
+            entry("common", "1.1.0", true, &[]),
+            entry("common", "1.2.0", true, &[]),
+            entry("unused", "0.1.0", true, &[]),
+            entry("a", "0.1.0", false, &[("b", "*")]),
+            entry("b", "0.1.0", false, &[("a", "*")]),
+        ] {
+            index.add(entry);
+        }
+
+        let graph = Graph::new(&index);
+        assert_eq!(graph.cycles, vec![vec!["a", "b"]]);
+        assert_eq!(graph.unreachable_libraries, ["unused"]);
+        assert_eq!(graph.conflicts.len(), 1);
+        assert_eq!(graph.conflicts[0].root, "app");
+        assert_eq!(graph.conflicts[0].chart, "common");
+        assert_eq!(
+            graph.conflicts[0].requirements,
+            [
+                Requirement {
+                    by: String::from("api"),
+                    constraint: String::from("~1.1.0")
+                },
+                Requirement {
+                    by: String::from("web"),
+                    constraint: String::from("~1.2.0")
+                },
+            ]
+        );
+        let redis = graph.nodes.iter().find(|n| n.name == "redis").unwrap();
+        assert_eq!(redis.version, None);
+
+        let dot = graph.to_dot();
+        assert!(
+            dot.contains("  \"common\" [label=\"common 1.2.0\", shape=box];\n"),
+            "{dot}"
+        );
+        assert!(
+            dot.contains("  \"redis\" [label=\"redis\", style=dashed];\n"),
+            "{dot}"
+        );
+        assert!(
+            dot.contains("  \"a\" -> \"b\" [label=\"*\", color=red];\n"),
+            "{dot}"
+        );
+
+        let mermaid = graph.to_mermaid();
+        assert!(
+            mermaid.starts_with("graph LR\n  n0[\"a 0.1.0\"]\n"),
+            "{mermaid}"
+        );
+        assert!(mermaid.contains("  n4[[\"common 1.2.0\"]]\n"), "{mermaid}");
+        assert!(mermaid.contains("  n2 -->|\"^1.0.0\"| n1\n"), "{mermaid}");
+    }
+}
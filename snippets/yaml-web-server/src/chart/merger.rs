@@ -1,27 +1,66 @@
 use std::error::Error;
-use std::{
-    fs::File,
-    path::{Path, PathBuf},
-};
+use std::fs;
+use std::path::{Path, PathBuf};
 
-use crate::Chart;
 use serde_yaml::Value;
 
-pub fn chart_from_file(override_path: &str, pb: PathBuf) -> Result<Chart, Box<dyn Error>> {
-    let chart_file = File::open(pb.clone())?;
-    let value: serde_yaml::Value = serde_yaml::from_reader(chart_file)?;
+use super::document::Document;
 
-    let override_: PathBuf = Path::new(override_path).join(pb);
-    if !override_.exists() || !override_.is_file() {
-        return Ok(serde_yaml::from_value::<Chart>(value)?);
+// The chart descriptor followed by every override of it that exists, lowest priority first.
+pub fn layer_paths(override_paths: &[PathBuf], pb: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![pb.to_path_buf()];
+
+    for override_path in override_paths {
+        let override_ = override_file(override_path, pb);
+        if override_.is_file() {
+            paths.push(override_);
+        }
     }
 
-    let override_file = File::open(override_)?;
-    let mut override_value: serde_yaml::Value = serde_yaml::from_reader(override_file)?;
+    paths
+}
 
-    merge(&value, &mut override_value);
+// Overrides mirror the chart path, so absolute chart roots are nested under each override too.
+pub fn override_file(override_path: &Path, pb: &Path) -> PathBuf {
+    override_path.join(pb.strip_prefix("/").unwrap_or(pb))
+}
 
-    Ok(serde_yaml::from_value::<Chart>(override_value)?)
+// Sets every leaf of `patch` in the chart's override file, creating it if needed. Whatever else
+// the file holds is left untouched, comments included.
+pub fn patch_override(
+    override_path: &Path,
+    pb: &Path,
+    patch: &Value,
+) -> Result<(), Box<dyn Error>> {
+    let file = override_file(override_path, pb);
+    let text = match fs::read_to_string(&file) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut document = Document::parse(text)?;
+    document.merge(patch)?;
+
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&file, document.as_str())?;
+    Ok(())
+}
+
+// Each layer is laid over the ones before it: its scalars replace theirs, mappings are merged key
+// by key and lists are joined.
+pub fn merge_layers(layers: Vec<Value>) -> Value {
+    let mut layers = layers.into_iter();
+    let mut value = layers.next().unwrap_or_default();
+
+    for mut override_value in layers {
+        merge(&value, &mut override_value);
+        value = override_value;
+    }
+
+    value
 }
 
 fn merge(src: &Value, dst: &mut Value) {
@@ -40,6 +79,64 @@ fn merge(src: &Value, dst: &mut Value) {
             dst.extend_from_slice(src);
         }
 
-        (src, dst) => *dst = src.clone(),
+        // The higher layer keeps its own value, whatever the lower one held.
+        _ => {}
+    }
+}
+
+// `overrides` laid over `defaults` the way Helm coalesces values, which differs from the layer
+// merge above: lists are replaced rather than joined, and a null drops the default underneath.
+pub fn coalesce(overrides: Value, defaults: &Value) -> Value {
+    match (overrides, defaults) {
+        (Value::Null, defaults) => defaults.clone(),
+        (Value::Mapping(mut overrides), Value::Mapping(defaults)) => {
+            for (key, default) in defaults {
+                match overrides.get_mut(key) {
+                    Some(Value::Null) => {}
+                    Some(value) => *value = coalesce(std::mem::take(value), default),
+                    None => {
+                        overrides.insert(key.clone(), default.clone());
+                    }
+                }
+            }
+            overrides.retain(|key, value| !(value.is_null() && defaults.contains_key(key)));
+            Value::Mapping(overrides)
+        }
+        (overrides, _) => overrides,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use super::{coalesce, merge_layers};
+
+    #[test]
+    fn test_coalesce_like_helm() {
+        let defaults: Value =
+            serde_yaml::from_str("image: {repository: app, tag: '1.0'}\nreplicas: 1\n").unwrap();
+        let overrides: Value =
+            serde_yaml::from_str("image: {tag: '2.0'}\nreplicas: null\nextra: true\n").unwrap();
+        let expected: Value =
+            serde_yaml::from_str("image: {tag: '2.0', repository: app}\nextra: true\n").unwrap();
+        assert_eq!(coalesce(overrides, &defaults), expected);
+    }
+
+    #[test]
+    fn test_later_layers_win() {
+        let layers: Vec<Value> = [
+            "name: app\nversion: 0.1.0\nkeywords: [web]\nannotations: {team: a, tier: gold}\n",
+            "version: 1.1.0\nannotations: {team: b}\n",
+            "keywords: [api]\nannotations: {tier: silver}\n",
+        ]
+        .iter()
+        .map(|layer| serde_yaml::from_str(layer).unwrap())
+        .collect();
+        let expected: Value = serde_yaml::from_str(
+            "version: 1.1.0\nannotations: {tier: silver, team: b}\nname: app\nkeywords: [api, web]\n",
+        )
+        .unwrap();
+        assert_eq!(merge_layers(layers), expected);
     }
 }
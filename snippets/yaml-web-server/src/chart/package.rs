@@ -0,0 +1,182 @@
+use std::error::Error;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use glob::Pattern;
+use sha2::{Digest, Sha256};
+
+const IGNORE_FILE: &str = ".helmignore";
+
+// Packages the folder holding `chart_file` the way `helm package` lays it out: every file nested
+// under a `<name>/` folder, with `chart_yaml` (the merged descriptor) written as `Chart.yaml`.
+// Timestamps are zeroed so that the same chart always produces the same digest.
+pub fn package(chart_file: &Path, name: &str, chart_yaml: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let chart_dir = chart_file
+        .parent()
+        .ok_or("chart descriptor has no parent folder")?;
+    let ignored = ignore_patterns(chart_dir)?;
+
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    append(
+        &mut builder,
+        &format!("{name}/Chart.yaml"),
+        chart_yaml.as_bytes(),
+    )?;
+
+    for file in files(chart_dir, &ignored)? {
+        let relative = file.strip_prefix(chart_dir)?;
+        if relative == Path::new("Chart.yaml")
+            || Some(relative.as_os_str()) == chart_file.file_name()
+        {
+            continue;
+        }
+
+        let path = format!("{name}/{}", relative.to_string_lossy().replace('\\', "/"));
+        append(&mut builder, &path, &fs::read(&file)?)?;
+    }
+
+    Ok(builder.into_inner()?.finish()?)
+}
+
+pub fn digest(archive: &[u8]) -> String {
+    hex::encode(Sha256::digest(archive))
+}
+
+// Reads `<name>/<file>` back out of a packaged chart.
+pub fn file_from_archive(archive: &[u8], file: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let mut tar = tar::Archive::new(GzDecoder::new(archive));
+
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let mut components = path.components();
+        if components.next().is_some() && components.as_path() == Path::new(file) {
+            let mut content = String::new();
+            entry.read_to_string(&mut content)?;
+            return Ok(Some(content));
+        }
+    }
+
+    Ok(None)
+}
+
+// A file with its path below the folder it was read from.
+pub type ArchivedFile = (String, Vec<u8>);
+
+// Every file under `<name>/<dir>/`, in archive order. Contents are left as bytes, so one file
+// that is not text does not keep the others from being read.
+pub fn files_from_archive(archive: &[u8], dir: &str) -> Result<Vec<ArchivedFile>, Box<dyn Error>> {
+    let mut tar = tar::Archive::new(GzDecoder::new(archive));
+    let mut files = Vec::new();
+
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let mut components = path.components();
+        components.next();
+        let Ok(relative) = components.as_path().strip_prefix(dir) else {
+            continue;
+        };
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content)?;
+        files.push((relative, content));
+    }
+
+    Ok(files)
+}
+
+pub fn chart_yaml_from_archive(archive: &[u8]) -> Result<String, Box<dyn Error>> {
+    file_from_archive(archive, "Chart.yaml")?
+        .ok_or_else(|| "archive does not contain a Chart.yaml".into())
+}
+
+// An archive with the file name it goes by.
+pub type NamedArchive = (String, Vec<u8>);
+
+// The subchart archives vendored under `<name>/charts/`.
+pub fn vendored_from_archive(archive: &[u8]) -> Result<Vec<NamedArchive>, Box<dyn Error>> {
+    let mut tar = tar::Archive::new(GzDecoder::new(archive));
+    let mut vendored = Vec::new();
+
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let mut components = path.components();
+        components.next();
+        let relative = components.as_path();
+        if relative.parent() != Some(Path::new("charts"))
+            || relative.extension().is_none_or(|e| e != "tgz")
+        {
+            continue;
+        }
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content)?;
+        let file_name = relative.file_name().unwrap_or_default().to_string_lossy();
+        vendored.push((file_name.to_string(), content));
+    }
+
+    Ok(vendored)
+}
+
+fn append<W: std::io::Write>(
+    builder: &mut tar::Builder<W>,
+    path: &str,
+    data: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    builder.append_data(&mut header, path, data)?;
+    Ok(())
+}
+
+fn files(dir: &Path, ignored: &[Pattern]) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<_, _>>()?;
+    entries.sort();
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let name = entry.file_name().unwrap_or_default().to_string_lossy();
+        if ignored.iter().any(|p| p.matches(&name)) {
+            continue;
+        }
+
+        if entry.is_dir() {
+            files.extend(self::files(&entry, ignored)?);
+        } else {
+            files.push(entry);
+        }
+    }
+
+    Ok(files)
+}
+
+// Only plain name globs from .helmignore are honoured; negations and path patterns are skipped.
+fn ignore_patterns(chart_dir: &Path) -> Result<Vec<Pattern>, Box<dyn Error>> {
+    let Ok(content) = fs::read_to_string(chart_dir.join(IGNORE_FILE)) else {
+        return Ok(Vec::new());
+    };
+
+    let mut patterns = Vec::new();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+            continue;
+        }
+        let line = line.trim_end_matches('/');
+        if line.contains('/') {
+            continue;
+        }
+        patterns.push(Pattern::new(line)?);
+    }
+
+    Ok(patterns)
+}
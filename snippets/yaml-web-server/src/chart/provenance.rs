@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::pgp::{Keyring, SecretKey};
+
+// YAML's `---` would break the clearsign block, so Helm separates the documents with `...`.
+const DOCUMENT_END: &str = "\n...\n";
+
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq)]
+pub struct SumCollection {
+    pub files: BTreeMap<String, String>,
+}
+
+pub fn message(chart_yaml: &str, file_name: &str, digest: &str) -> Result<String, Box<dyn Error>> {
+    let sums = SumCollection {
+        files: BTreeMap::from([(file_name.to_string(), format!("sha256:{digest}"))]),
+    };
+
+    let mut message = chart_yaml.trim_end_matches('\n').to_string();
+    message.push('\n');
+    message.push_str(DOCUMENT_END);
+    message.push_str(&serde_yaml::to_string(&sums)?);
+
+    Ok(message)
+}
+
+pub fn sign(
+    key: &SecretKey,
+    chart_yaml: &str,
+    file_name: &str,
+    digest: &str,
+    created: SystemTime,
+) -> Result<String, Box<dyn Error>> {
+    key.clearsign(&message(chart_yaml, file_name, digest)?, created)
+}
+
+// Checks the signature against the keyring and that it covers an archive with `digest`.
+pub fn verify(
+    keyring: &Keyring,
+    provenance: &str,
+    file_name: &str,
+    digest: &str,
+) -> Result<(), Box<dyn Error>> {
+    let text = keyring.verify_clearsigned(provenance)?;
+    let (_, sums) = text
+        .split_once(DOCUMENT_END)
+        .ok_or("provenance file has no checksum section")?;
+    let sums: SumCollection = serde_yaml::from_str(sums)?;
+
+    match sums.files.get(file_name) {
+        Some(sum) if *sum == format!("sha256:{digest}") => Ok(()),
+        Some(_) => {
+            Err(format!("provenance digest for {file_name} does not match the archive").into())
+        }
+        None => Err(format!("provenance file does not cover {file_name}").into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::SystemTime;
+
+    use super::{sign, verify};
+    use crate::pgp::{Keyring, SecretKey};
+
+    #[test]
+    fn test_verify_checks_archive_digest() {
+        let key =
+            SecretKey::from_bytes(include_bytes!("../../testdata/pgp/signer.sec.asc")).unwrap();
+        let keyring =
+            Keyring::from_bytes(include_bytes!("../../testdata/pgp/signer.pub.asc")).unwrap();
+        let chart_yaml = "apiVersion: v2\nname: test-chart-1\nversion: 0.1.0-slug\n";
+
+        let prov = sign(
+            &key,
+            chart_yaml,
+            "test-chart-1-0.1.0-slug.tgz",
+            "abc",
+            SystemTime::now(),
+        )
+        .unwrap();
+
+        assert!(prov.contains("\n...\nfiles:\n  test-chart-1-0.1.0-slug.tgz: sha256:abc\n"));
+        verify(&keyring, &prov, "test-chart-1-0.1.0-slug.tgz", "abc").unwrap();
+        assert!(verify(&keyring, &prov, "test-chart-1-0.1.0-slug.tgz", "def").is_err());
+        assert!(verify(&keyring, &prov, "other-0.1.0.tgz", "abc").is_err());
+    }
+}
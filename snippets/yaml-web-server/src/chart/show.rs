@@ -0,0 +1,212 @@
+// `helm show`: what a packaged chart holds, read out of the archive so nobody has to download
+// and unpack it to find out.
+use std::error::Error;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use serde_yaml::Value;
+
+use super::package;
+
+const CRDS_DIR: &str = "crds";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Part {
+    Chart,
+    Values,
+    Readme,
+    Crds,
+    All,
+}
+
+impl FromStr for Part {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Part, String> {
+        match value {
+            "chart" => Ok(Part::Chart),
+            "values" => Ok(Part::Values),
+            "readme" => Ok(Part::Readme),
+            "crds" => Ok(Part::Crds),
+            "all" => Ok(Part::All),
+            _ => Err(format!(
+                "`{value}` is not one of chart, values, readme, crds or all"
+            )),
+        }
+    }
+}
+
+// The files as packaged, comments included: Chart.yaml has the overrides merged in, values.yaml
+// is the chart's own. The values a chart is installed with, subcharts included, are the values
+// endpoint's business.
+#[derive(Debug)]
+pub struct Shown {
+    chart_yaml: String,
+    values_yaml: Option<String>,
+    readme: Option<String>,
+    crds: Result<Vec<Crd>, String>, // A broken CRD only fails the parts that show CRDs.
+}
+
+#[derive(Debug, Serialize)]
+pub struct Crd {
+    pub file: String, // Below `crds/`.
+    #[serde(skip)]
+    content: String,
+    pub documents: Vec<Value>,
+}
+
+impl Shown {
+    pub fn new(archive: &[u8]) -> Result<Shown, Box<dyn Error>> {
+        Ok(Shown {
+            chart_yaml: package::chart_yaml_from_archive(archive)?,
+            values_yaml: package::file_from_archive(archive, "values.yaml")?,
+            readme: package::file_from_archive(archive, "README.md")?,
+            crds: crds(archive),
+        })
+    }
+
+    // The part as `helm show` prints it. `all` joins the others as separate YAML documents.
+    pub fn yaml(&self, part: Part) -> Result<String, Box<dyn Error>> {
+        let text = match part {
+            Part::Chart => self.chart_yaml.clone(),
+            Part::Values => self.values_yaml.clone().unwrap_or_default(),
+            Part::Readme => self.readme.clone().unwrap_or_default(),
+            Part::Crds => {
+                let crds = self.crds.as_ref().map_err(String::as_str)?;
+                let crds: Vec<&str> = crds.iter().map(|c| c.content.as_str()).collect();
+                crds.join("---\n")
+            }
+            Part::All => {
+                let mut parts = vec![
+                    self.yaml(Part::Chart)?,
+                    self.yaml(Part::Values)?,
+                    self.yaml(Part::Readme)?,
+                ];
+                let crds = self.crds.as_ref().map_err(String::as_str)?;
+                parts.extend(crds.iter().map(|c| c.content.clone()));
+                parts
+                    .iter()
+                    .map(|part| terminated(part))
+                    .collect::<Vec<_>>()
+                    .join("---\n")
+            }
+        };
+        Ok(terminated(&text))
+    }
+
+    pub fn json(&self, part: Part) -> Result<serde_json::Value, Box<dyn Error>> {
+        let json = match part {
+            Part::Chart => serde_json::to_value(serde_yaml::from_str::<Value>(&self.chart_yaml)?)?,
+            Part::Values => match &self.values_yaml {
+                Some(values) => serde_json::to_value(serde_yaml::from_str::<Value>(values)?)?,
+                None => serde_json::Value::Null,
+            },
+            Part::Readme => serde_json::to_value(&self.readme)?,
+            Part::Crds => serde_json::to_value(self.crds.as_ref().map_err(String::as_str)?)?,
+            Part::All => json!({
+                "chart": self.json(Part::Chart)?,
+                "values": self.json(Part::Values)?,
+                "readme": self.json(Part::Readme)?,
+                "crds": self.json(Part::Crds)?,
+            }),
+        };
+        Ok(json)
+    }
+}
+
+// Like Helm, only manifests count; anything else in `crds/` is not applied.
+fn crds(archive: &[u8]) -> Result<Vec<Crd>, String> {
+    let files = package::files_from_archive(archive, CRDS_DIR).map_err(|e| e.to_string())?;
+    let mut crds = Vec::new();
+    for (file, content) in files {
+        if ![".yaml", ".yml", ".json"].iter().any(|e| file.ends_with(e)) {
+            continue;
+        }
+        let content = String::from_utf8(content)
+            .map_err(|_| format!("{CRDS_DIR}/{file} is not UTF-8 text"))?;
+        let mut documents = Vec::new();
+        for document in serde_yaml::Deserializer::from_str(&content) {
+            match Value::deserialize(document) {
+                Ok(Value::Null) => {}
+                Ok(document) => documents.push(document),
+                Err(err) => return Err(format!("{CRDS_DIR}/{file}: {err}")),
+            }
+        }
+        crds.push(Crd {
+            file,
+            content,
+            documents,
+        });
+    }
+    Ok(crds)
+}
+
+fn terminated(text: &str) -> String {
+    if text.is_empty() || text.ends_with('\n') {
+        return text.to_string();
+    }
+    format!("{text}\n")
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use serde_json::json;
+
+    use super::{Part, Shown};
+    use crate::chart::package;
+
+    #[test]
+    fn test_show_reads_the_archive() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        fs::create_dir_all(dir.join("crds")).unwrap();
+        fs::write(dir.join("Chart.yaml"), "").unwrap();
+        fs::write(dir.join("README.md"), "# App").unwrap();
+        let values_yaml = "# How many pods to run.\nreplicas: 2\ncache:\n  size: 1Gi # Per pod.\n";
+        fs::write(dir.join("values.yaml"), values_yaml).unwrap();
+        fs::write(
+            dir.join("crds/widgets.yaml"),
+            "kind: CustomResourceDefinition\nmetadata:\n  name: widgets.example.com\n---\nkind: CustomResourceDefinition\nmetadata:\n  name: gadgets.example.com\n",
+        )
+        .unwrap();
+        fs::write(dir.join("crds/README.txt"), "not a manifest").unwrap();
+        let chart_yaml = "# Merged\napiVersion: v2\nname: app\nversion: 1.0.0\n";
+        let archive = package::package(&dir.join("Chart.yaml"), "app", chart_yaml).unwrap();
+        let shown = Shown::new(&archive).unwrap();
+
+        assert_eq!(shown.yaml(Part::Chart).unwrap(), chart_yaml);
+        assert_eq!(shown.yaml(Part::Values).unwrap(), values_yaml);
+        assert_eq!(shown.yaml(Part::Readme).unwrap(), "# App\n");
+        assert_eq!(
+            shown.yaml(Part::All).unwrap(),
+            format!("{chart_yaml}---\n{values_yaml}---\n# App\n---\nkind: CustomResourceDefinition\nmetadata:\n  name: widgets.example.com\n---\nkind: CustomResourceDefinition\nmetadata:\n  name: gadgets.example.com\n")
+        );
+        assert_eq!(
+            shown.json(Part::Crds).unwrap(),
+            json!([{
+                "file": "widgets.yaml",
+                "documents": [
+                    { "kind": "CustomResourceDefinition", "metadata": { "name": "widgets.example.com" } },
+                    { "kind": "CustomResourceDefinition", "metadata": { "name": "gadgets.example.com" } },
+                ],
+            }])
+        );
+        assert_eq!(shown.json(Part::Values).unwrap()["cache"]["size"], "1Gi");
+        assert_eq!(shown.json(Part::All).unwrap()["chart"]["name"], "app");
+        assert_eq!("everything".parse::<Part>().ok(), None);
+
+        // A CRD that cannot be read only fails the parts that show it.
+        fs::write(dir.join("crds/broken.yaml"), b"\xff\xfe").unwrap();
+        let archive = package::package(&dir.join("Chart.yaml"), "app", chart_yaml).unwrap();
+        let shown = Shown::new(&archive).unwrap();
+        assert_eq!(shown.yaml(Part::Chart).unwrap(), chart_yaml);
+        assert_eq!(shown.yaml(Part::Values).unwrap(), values_yaml);
+        let err = shown.yaml(Part::Crds).unwrap_err();
+        assert_eq!(err.to_string(), "crds/broken.yaml is not UTF-8 text");
+        assert!(shown.yaml(Part::All).is_err());
+        assert!(shown.json(Part::All).is_err());
+    }
+}
@@ -1,88 +1,278 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::fmt;
-use std::path::Display;
+use std::str::FromStr;
 
 use regex::Regex;
 use serde::de::{self, Deserializer, Visitor};
-use serde::{ser, Deserialize, Serialize, Serializer};
+use serde::{Deserialize, Serialize, Serializer};
 
-#[derive(Debug, Deserialize, Default, Clone, PartialEq)]
+// Chart.yaml for apiVersion v1 and v2 charts. Keys this does not know about are kept in `extra`
+// and written back, so a chart can be read, changed and repackaged without losing anything.
+#[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq)]
 pub struct Chart {
     #[serde(rename = "apiVersion")]
     pub api_version: String,
     pub name: String,
     pub version: Version,
     #[serde(rename = "kubeVersion")]
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub kube_version: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub description: String,
     #[serde(rename = "type")]
-    pub type_: String,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub type_: String, // `application` or `library`; v1 charts have none.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub keywords: Vec<String>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub home: String,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub sources: Vec<String>,
-    #[serde(default)]
-    pub dependencies: Vec<Dependency>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<Dependency>, // v1 charts list them in requirements.yaml instead.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub maintainers: Vec<Maintainer>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub icon: String,
     #[serde(rename = "appVersion")]
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub app_version: String, // The version of the app that this contains (optional). Needn't be SemVer.
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "is_false")]
     pub deprecated: bool,
-    #[serde(default)]
-    pub annotations: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub engine: String, // v1 only, always `gotpl`.
+    #[serde(rename = "tillerVersion")]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub tiller_version: String, // v1 only.
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_yaml::Value>,
+}
+
+// Helm's rule for chart names. Names end up in file names and URLs, so a name from an uploaded
+// chart or an upstream index is checked before it is used for either.
+pub fn validate_name(name: &str) -> Result<(), String> {
+    let re = Regex::new(r"^[a-z0-9]([-a-z0-9]*[a-z0-9])?$")
+        .map_err(|e| format!("error compiling regex: {e}"))?;
+    if !re.is_match(name) {
+        return Err(format!(
+            "chart name `{name}` must be lowercase letters, digits and dashes"
+        ));
+    }
+    Ok(())
 }
 
-#[derive(Debug, Default, Clone, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct Version {
     pub major: i32,
     pub minor: i32,
     pub bugfix: i32,
     pub slug: String,
+    pub build: String, // Build metadata after `+`, such as the upstream version in `1.2.3+up4.5.6`.
 }
 
 impl Version {
-    fn assemble_version(self) -> &str {
-        let without_slug = format!("{}.{}.{}", v.major, v.minor, v.bugfix).as_str();
-        let with_slug = format!("{}.{}.{}-{}", v.major, v.minor, v.bugfix, v.slug).as_str();
-        if v.slug == "" {
-            without_slug
-        } else {
-            with_slug
+    fn assemble_version(&self) -> String {
+        let mut version = format!("{}.{}.{}", self.major, self.minor, self.bugfix);
+        if !self.slug.is_empty() {
+            version.push('-');
+            version.push_str(&self.slug);
         }
+        if !self.build.is_empty() {
+            version.push('+');
+            version.push_str(&self.build);
+        }
+        version
     }
 }
 
 #[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq)]
 pub struct Dependency {
     pub name: String,
-    pub version: Version,
-    #[serde(default)]
+    pub version: String, // A constraint such as `~1.2.0` or `>=1.0.0 <2.0.0`, not a single version.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub repository: String,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub condition: String,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub enabled: bool,
+    // Either child value paths or `{child, parent}` mappings.
     #[serde(rename = "import-values")]
-    #[serde(default)]
-    pub import_values: Vec<String>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub import_values: Vec<serde_yaml::Value>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub alias: String,
 }
 
 #[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq)]
 pub struct Maintainer {
     pub name: String,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub email: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub url: String,
 }
 
+// Which part of a version a release moves on.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Bump {
+    Major,
+    Minor,
+    Patch,
+    Prerelease,
+}
+
+impl FromStr for Bump {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Bump, String> {
+        match value {
+            "major" => Ok(Bump::Major),
+            "minor" => Ok(Bump::Minor),
+            "patch" => Ok(Bump::Patch),
+            "prerelease" => Ok(Bump::Prerelease),
+            _ => Err(format!(
+                "`{value}` is not one of major, minor, patch or prerelease"
+            )),
+        }
+    }
+}
+
+impl Version {
+    // Follows `npm version`: a prerelease bumps to the release it leads up to, and bumping the
+    // prerelease counts its last numeric identifier up, starting one at `.0` when there is none.
+    pub fn bump(&self, part: Bump) -> Result<Version, String> {
+        let (major, minor, bugfix) = (self.major, self.minor, self.bugfix);
+        let pre = !self.slug.is_empty();
+        let release = |major, minor, bugfix| Version {
+            major,
+            minor,
+            bugfix,
+            slug: String::new(),
+            build: String::new(),
+        };
+        let exhausted = || format!("version {self} cannot be bumped any further");
+        let next = |n: i32| n.checked_add(1).ok_or_else(exhausted);
+
+        let version = match part {
+            Bump::Major if pre && minor == 0 && bugfix == 0 => release(major, 0, 0),
+            Bump::Major => release(next(major)?, 0, 0),
+            Bump::Minor if pre && bugfix == 0 => release(major, minor, 0),
+            Bump::Minor => release(major, next(minor)?, 0),
+            Bump::Patch if pre => release(major, minor, bugfix),
+            Bump::Patch => release(major, minor, next(bugfix)?),
+            Bump::Prerelease if !pre => Version {
+                slug: String::from("0"),
+                ..release(major, minor, next(bugfix)?)
+            },
+            Bump::Prerelease => {
+                let mut identifiers: Vec<String> =
+                    self.slug.split('.').map(str::to_string).collect();
+                match identifiers.iter_mut().rev().find_map(|i| {
+                    let n = i.parse::<u64>().ok()?;
+                    Some((i, n))
+                }) {
+                    Some((identifier, n)) => {
+                        *identifier = n.checked_add(1).ok_or_else(exhausted)?.to_string()
+                    }
+                    None => identifiers.push(String::from("0")),
+                }
+                Version {
+                    slug: identifiers.join("."),
+                    ..release(major, minor, bugfix)
+                }
+            }
+        };
+        Ok(version)
+    }
+}
+
+impl FromStr for Version {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Version, String> {
+        let re = Regex::new(r"^(?P<major>\d+)\.(?P<minor>\d+)\.(?P<bugfix>\d+)(-(?P<slug>[^+]*))?(\+(?P<build>.*))?$")
+            .map_err(|e| format!("error compiling regex: {e}"))?;
+        let capture = re
+            .captures(value)
+            .ok_or_else(|| format!("version {value} does not match semver regex"))?;
+        let number = |name: &str| {
+            capture[name]
+                .parse::<i32>()
+                .map_err(|e| format!("version {value}: {e}"))
+        };
+
+        Ok(Version {
+            major: number("major")?,
+            minor: number("minor")?,
+            bugfix: number("bugfix")?,
+            slug: capture
+                .name("slug")
+                .map_or(String::new(), |slug| slug.as_str().to_string()),
+            build: capture
+                .name("build")
+                .map_or(String::new(), |build| build.as_str().to_string()),
+        })
+    }
+}
+
+// SemVer precedence: a prerelease comes before its release, and prerelease identifiers compare
+// numerically when both are numbers. Identifiers with the same number written differently, `01`
+// and `1`, are told apart by their text so that the order agrees with `==`. Build metadata has
+// no say in precedence and likewise only breaks ties.
+impl Ord for Version {
+    fn cmp(&self, other: &Version) -> Ordering {
+        self.precedence(other)
+            .then_with(|| self.build.cmp(&other.build))
+    }
+}
+
+impl Version {
+    fn precedence(&self, other: &Version) -> Ordering {
+        let release =
+            (self.major, self.minor, self.bugfix).cmp(&(other.major, other.minor, other.bugfix));
+        if release != Ordering::Equal {
+            return release;
+        }
+
+        match (self.slug.is_empty(), other.slug.is_empty()) {
+            (true, true) => return Ordering::Equal,
+            (true, false) => return Ordering::Greater,
+            (false, true) => return Ordering::Less,
+            (false, false) => {}
+        }
+        let mut ours = self.slug.split('.');
+        let mut theirs = other.slug.split('.');
+        loop {
+            let ordering = match (ours.next(), theirs.next()) {
+                (None, None) => return Ordering::Equal,
+                (None, Some(_)) => return Ordering::Less,
+                (Some(_), None) => return Ordering::Greater,
+                (Some(a), Some(b)) => match (a.parse::<u64>(), b.parse::<u64>()) {
+                    (Ok(x), Ok(y)) => x.cmp(&y).then_with(|| a.cmp(b)),
+                    (Ok(_), Err(_)) => Ordering::Less,
+                    (Err(_), Ok(_)) => Ordering::Greater,
+                    (Err(_), Err(_)) => a.cmp(b),
+                },
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Version) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl<'de> serde::de::Deserialize<'de> for Version {
     fn deserialize<D>(deserializer: D) -> Result<Version, D::Error>
     where
@@ -101,21 +291,7 @@ impl<'de> serde::de::Deserialize<'de> for Version {
             where
                 E: de::Error,
             {
-                let re =
-                    Regex::new(r"^(?P<major>\d*)\.(?P<minor>\d*)\.(?P<bugfix>\d*)-(?P<slug>.*)")
-                        .map_err(|e| de::Error::custom(format!("error compiling regex: {e}")))?;
-
-                match re.captures(value) {
-                    Some(capture) => Ok(Version {
-                        major: capture["major"].parse::<i32>().unwrap(),
-                        minor: capture["minor"].parse::<i32>().unwrap(),
-                        bugfix: capture["bugfix"].parse::<i32>().unwrap(),
-                        slug: capture["slug"].to_string(),
-                    }),
-                    None => Err(de::Error::custom(format!(
-                        "version {value} does not match semver regex"
-                    ))),
-                }
+                value.parse().map_err(de::Error::custom)
             }
         }
 
@@ -134,87 +310,221 @@ impl Serialize for Version {
     where
         S: Serializer,
     {
-        serializer.serialize_str(self.assemble_version())
+        serializer.serialize_str(&self.assemble_version())
     }
 }
 
+// index.yaml as Helm writes it. Empty optional fields are left out again when serializing, so a
+// parsed index writes back the way it was read.
 #[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq)]
 pub struct Repository {
     #[serde(rename = "apiVersion")]
     pub api_version: String,
     pub generated: String,
-    pub entries: Vec<RepositoryEntry>,
+    pub entries: BTreeMap<String, Vec<RepositoryEntry>>,
+    #[serde(rename = "serverInfo")]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub server_info: BTreeMap<String, serde_yaml::Value>,
+    #[serde(rename = "publicKeys")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub public_keys: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+}
+
+impl Repository {
+    pub fn new() -> Repository {
+        Repository {
+            api_version: String::from("v1"),
+            generated: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Nanos, true),
+            entries: BTreeMap::new(),
+            server_info: BTreeMap::new(),
+            public_keys: Vec::new(),
+            annotations: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, entry: RepositoryEntry) {
+        self.entries
+            .entry(entry.name.clone())
+            .or_default()
+            .push(entry);
+    }
+
+    // Merges named indexes, highest priority first. A version already taken by an earlier
+    // source is dropped from the later ones, and reported when the two archives differ.
+    pub fn merge(sources: Vec<(String, Repository)>) -> (Repository, Vec<Conflict>) {
+        let mut merged = Repository::new();
+        let mut owners: BTreeMap<(String, String), (String, String)> = BTreeMap::new();
+        let mut conflicts = Vec::new();
+
+        for (source, index) in sources {
+            for entry in index.entries.into_values().flatten() {
+                let key = (entry.name.clone(), entry.version.to_string());
+                match owners.get(&key) {
+                    Some((kept, digest)) => {
+                        if *digest != entry.digest {
+                            conflicts.push(Conflict {
+                                name: entry.name,
+                                version: entry.version,
+                                kept: kept.clone(),
+                                kept_digest: digest.clone(),
+                                dropped: source.clone(),
+                                dropped_digest: entry.digest,
+                            });
+                        }
+                    }
+                    None => {
+                        owners.insert(key, (source.clone(), entry.digest.clone()));
+                        merged.add(entry);
+                    }
+                }
+            }
+        }
+
+        (merged, conflicts)
+    }
+}
+
+// The same chart version offered by two sources with different archives.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub name: String,
+    pub version: Version,
+    pub kept: String, // The source whose archive is served.
+    pub kept_digest: String,
+    pub dropped: String,
+    pub dropped_digest: String,
 }
 
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} differs between {} (digest {}) and {} (digest {}), serving the one from {}",
+            self.name,
+            self.version,
+            self.kept,
+            self.kept_digest,
+            self.dropped,
+            self.dropped_digest,
+            self.kept
+        )
+    }
+}
+
+// One chart version in the index: the chart metadata plus where to download it.
 #[derive(Debug, Deserialize, Serialize, Default, Clone, PartialEq)]
 pub struct RepositoryEntry {
     #[serde(rename = "apiVersion")]
     pub api_version: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub created: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub description: String,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub digest: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub urls: Vec<String>,
     pub version: Version,
     #[serde(rename = "kubeVersion")]
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub kube_version: String,
     #[serde(rename = "type")]
-    pub type_: String,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub type_: String, // Missing from apiVersion v1 charts.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub keywords: Vec<String>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub home: String,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub sources: Vec<String>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub dependencies: Vec<Dependency>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub maintainers: Vec<Maintainer>,
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub icon: String,
     #[serde(rename = "appVersion")]
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub app_version: String, // The version of the app that this contains (optional). Needn't be SemVer.
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "is_false")]
     pub deprecated: bool,
-    #[serde(default)]
-    pub annotations: Vec<String>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub removed: bool,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+impl From<&Chart> for RepositoryEntry {
+    fn from(chart: &Chart) -> Self {
+        RepositoryEntry {
+            api_version: chart.api_version.clone(),
+            name: chart.name.clone(),
+            created: String::new(),
+            description: chart.description.clone(),
+            digest: String::new(),
+            urls: Vec::new(),
+            version: chart.version.clone(),
+            kube_version: chart.kube_version.clone(),
+            type_: chart.type_.clone(),
+            keywords: chart.keywords.clone(),
+            home: chart.home.clone(),
+            sources: chart.sources.clone(),
+            dependencies: chart.dependencies.clone(),
+            maintainers: chart.maintainers.clone(),
+            icon: chart.icon.clone(),
+            app_version: chart.app_version.clone(),
+            deprecated: chart.deprecated,
+            removed: false,
+            annotations: chart.annotations.clone(),
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{Chart, Repository, Version};
-
-    // #[test]
-    // fn test_deserialize_chart_yaml() {
-    //     let yaml: Chart = serde_yaml::from_str(
-    //         r#"
-    //     apiVersion: v2
-    //     name: test-chart-1
-    //     description: A Helm chart for Kubernetes
-    //     type: application
-    //     version: 0.1.0
-    //     appVersion: "1.16.0"
-    //     "#,
-    //     )
-    //     .unwrap();
-    //
-    //     let expected_output = Chart {
-    //         api_version: String::from("v2"),
-    //         name: String::from("test-chart-1"),
-    //         description: String::from("A Helm chart for Kubernetes"),
-    //         type_: String::from("application"),
-    //         version: Version {
-    //             major: 0,
-    //             minor: 1,
-    //             bugfix: 0,
-    //         },
-    //         app_version: String::from("1.16.0"),
-    //         ..Default::default()
-    //     };
-    //
-    //     assert_eq!(yaml, expected_output);
-    // }
+    use std::collections::BTreeMap;
+
+    use super::{Bump, Chart, Repository, RepositoryEntry, Version};
+
+    #[test]
+    fn test_deserialize_chart_yaml() {
+        let yaml: Chart = serde_yaml::from_str(
+            r#"
+        apiVersion: v2
+        name: test-chart-1
+        description: A Helm chart for Kubernetes
+        type: application
+        version: 0.1.0
+        appVersion: "1.16.0"
+        "#,
+        )
+        .unwrap();
+
+        let expected_output = Chart {
+            api_version: String::from("v2"),
+            name: String::from("test-chart-1"),
+            description: String::from("A Helm chart for Kubernetes"),
+            type_: String::from("application"),
+            version: Version {
+                major: 0,
+                minor: 1,
+                bugfix: 0,
+                slug: String::new(),
+                ..Default::default()
+            },
+            app_version: String::from("1.16.0"),
+            ..Default::default()
+        };
+
+        assert_eq!(yaml, expected_output);
+    }
 
     #[test]
     fn test_deserialize_chart_yaml_with_slug() {
@@ -240,6 +550,7 @@ mod test {
                 minor: 1,
                 bugfix: 0,
                 slug: "slug".to_string(),
+                ..Default::default()
             },
             app_version: String::from("1.16.0"),
             ..Default::default()
@@ -248,6 +559,182 @@ mod test {
         assert_eq!(yaml, expected_output);
     }
 
+    #[test]
+    fn test_chart_yaml_round_trips() {
+        let v2 = r#"
+        apiVersion: v2
+        name: nri-bundle
+        version: 5.0.55
+        kubeVersion: ">=1.21.0-0"
+        description: Groups together the individual charts for the New Relic Kubernetes solution
+        type: application
+        keywords: [infrastructure, newrelic]
+        home: https://github.com/newrelic/helm-charts
+        sources: [https://github.com/newrelic/helm-charts/tree/master/charts/nri-bundle]
+        dependencies:
+            -   name: newrelic-infrastructure
+                version: 3.28.0
+                repository: https://newrelic.github.io/nri-kubernetes
+                condition: infrastructure.enabled
+                enabled: true
+            -   name: common-library
+                version: ~1.1.0
+                repository: "@newrelic"
+                import-values:
+                    -   child: defaults
+                        parent: global
+        maintainers:
+            -   name: juanjjaramillo
+                url: https://github.com/juanjjaramillo
+            -   name: New Relic
+                email: support@newrelic.com
+        icon: https://newrelic.com/logo.svg
+        appVersion: "1.0"
+        deprecated: true
+        annotations:
+            artifacthub.io/license: Apache-2.0
+        x-internal-team: observability
+        "#;
+        let v1 = r#"
+        apiVersion: v1
+        name: legacy
+        version: 0.3.1
+        description: A chart packaged for Helm 2
+        engine: gotpl
+        tillerVersion: ">2.0.0"
+        "#;
+
+        for original in [v2, v1] {
+            let chart: Chart = serde_yaml::from_str(original).unwrap();
+            let written = serde_yaml::to_string(&chart).unwrap();
+
+            let original: serde_yaml::Value = serde_yaml::from_str(original).unwrap();
+            let written: serde_yaml::Value = serde_yaml::from_str(&written).unwrap();
+            assert_eq!(written, original);
+        }
+
+        let chart: Chart = serde_yaml::from_str(v2).unwrap();
+        assert!(chart.dependencies[0].enabled);
+        assert_eq!(chart.maintainers[1].url, "");
+        assert_eq!(
+            chart.extra["x-internal-team"],
+            serde_yaml::Value::from("observability")
+        );
+    }
+
+    #[test]
+    fn test_version_bumps_and_precedence() {
+        let bump = |v: &str, part: Bump| {
+            let version = v.parse::<Version>().unwrap();
+            version.bump(part).unwrap().to_string()
+        };
+        assert_eq!(bump("1.2.3", Bump::Major), "2.0.0");
+        assert_eq!(bump("1.2.3", Bump::Minor), "1.3.0");
+        assert_eq!(bump("1.2.3", Bump::Patch), "1.2.4");
+        assert_eq!(bump("1.2.3", Bump::Prerelease), "1.2.4-0");
+        assert_eq!(bump("1.3.0-rc.1", Bump::Prerelease), "1.3.0-rc.2");
+        assert_eq!(bump("1.3.0-rc.1", Bump::Minor), "1.3.0");
+        assert_eq!(bump("1.3.0-beta", Bump::Prerelease), "1.3.0-beta.0");
+        assert_eq!(bump("2.0.0-rc.0", Bump::Major), "2.0.0");
+        let last = format!("{}.0.0", i32::MAX).parse::<Version>().unwrap();
+        assert!(last.bump(Bump::Major).is_err());
+        assert_eq!(
+            last.bump(Bump::Minor).unwrap().to_string(),
+            format!("{}.1.0", i32::MAX)
+        );
+        let last = format!("1.0.0-rc.{}", u64::MAX).parse::<Version>().unwrap();
+        assert!(last.bump(Bump::Prerelease).is_err());
+
+        let mut versions: Vec<Version> = [
+            "1.10.0",
+            "1.2.0",
+            "1.2.0-rc.10",
+            "1.2.0-rc.2",
+            "1.2.0-alpha",
+        ]
+        .iter()
+        .map(|v| v.parse().unwrap())
+        .collect();
+        versions.sort();
+        let sorted: Vec<String> = versions.iter().map(Version::to_string).collect();
+        assert_eq!(
+            sorted,
+            [
+                "1.2.0-alpha",
+                "1.2.0-rc.2",
+                "1.2.0-rc.10",
+                "1.2.0",
+                "1.10.0"
+            ]
+        );
+
+        // Equal precedence, but not the same version: the order has to agree with `==`.
+        let (leading_zero, plain): (Version, Version) =
+            ("1.0.0-01".parse().unwrap(), "1.0.0-1".parse().unwrap());
+        assert_ne!(leading_zero, plain);
+        assert_ne!(leading_zero.cmp(&plain), std::cmp::Ordering::Equal);
+        assert!(leading_zero < "1.0.0-2".parse().unwrap());
+
+        // Build metadata is kept apart from the prerelease and only breaks ties.
+        let built: Version = "1.0.0-rc.1+b.7".parse().unwrap();
+        assert_eq!((built.slug.as_str(), built.build.as_str()), ("rc.1", "b.7"));
+        assert_eq!(built.to_string(), "1.0.0-rc.1+b.7");
+        assert_eq!("1.2.3+up4.5.6".parse::<Version>().unwrap().build, "up4.5.6");
+        assert!(built > "1.0.0-rc.1".parse().unwrap());
+        assert!(built < "1.0.0-rc.2".parse().unwrap());
+        assert_eq!(bump("1.2.3+up4.5.6", Bump::Patch), "1.2.4");
+        assert_eq!(bump("1.0.0-rc.1+b.7", Bump::Prerelease), "1.0.0-rc.2");
+    }
+
+    #[test]
+    fn test_merge_reports_conflicting_digests() {
+        let entry = |name: &str, version: &str, digest: &str| RepositoryEntry {
+            name: name.to_string(),
+            version: serde_yaml::from_str(version).unwrap(),
+            digest: digest.to_string(),
+            ..Default::default()
+        };
+        let index = |entries: Vec<RepositoryEntry>| {
+            let mut index = Repository::new();
+            entries.into_iter().for_each(|e| index.add(e));
+            index
+        };
+
+        let (merged, conflicts) = Repository::merge(vec![
+            (
+                String::from("local"),
+                index(vec![entry("a", "1.0.0", "aaa"), entry("b", "1.0.0", "bbb")]),
+            ),
+            (
+                String::from("uploads"),
+                index(vec![entry("a", "1.0.0", "aaa"), entry("b", "1.0.0", "ccc")]),
+            ),
+            (
+                String::from("upstream"),
+                index(vec![entry("b", "2.0.0", "ddd")]),
+            ),
+        ]);
+
+        assert_eq!(merged.entries["a"].len(), 1);
+        let b: Vec<(String, &str)> = merged.entries["b"]
+            .iter()
+            .map(|e| (e.version.to_string(), e.digest.as_str()))
+            .collect();
+        assert_eq!(
+            b,
+            vec![
+                (String::from("1.0.0"), "bbb"),
+                (String::from("2.0.0"), "ddd")
+            ]
+        );
+
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].name, "b");
+        assert_eq!(conflicts[0].kept, "local");
+        assert_eq!(conflicts[0].dropped, "uploads");
+        assert_eq!(conflicts[0].dropped_digest, "ccc");
+    }
+
     #[test]
     fn test_deserializa_repo_index() {
         let yaml: Repository = serde_yaml::from_str(
@@ -272,20 +759,116 @@ mod test {
         ).unwrap();
 
         let expected_output = Repository {
-            api_version: String::from("v2"),
-            name: String::from("test-chart-1"),
-            description: String::from("A Helm chart for Kubernetes"),
-            type_: String::from("application"),
-            version: Version {
-                major: 0,
-                minor: 1,
-                bugfix: 0,
-                slug: "slug".to_string(),
-            },
-            app_version: String::from("1.16.0"),
+            api_version: String::from("v1"),
+            generated: String::from("2023-12-20T14:26:26.392635056Z"),
+            entries: BTreeMap::from([(
+                String::from("common-library"),
+                vec![RepositoryEntry {
+                    api_version: String::from("v2"),
+                    name: String::from("common-library"),
+                    created: String::from("2023-03-06T16:54:27.78965245Z"),
+                    description: String::from(
+                        "Provides helpers to provide consistency on all the charts",
+                    ),
+                    digest: String::from(
+                        "1df82a701109e29771912be9964eec8e954d49b2afa319d3ef01f5dff5164ecc",
+                    ),
+                    urls: vec![String::from("https://github.com/newrelic/helm-charts/releases/download/common-library-1.1.1/common-library-1.1.1.tgz")],
+                    version: Version {
+                        major: 1,
+                        minor: 1,
+                        bugfix: 1,
+                        slug: String::new(),
+                        ..Default::default()
+                    },
+                    type_: String::from("library"),
+                    keywords: vec![String::from("newrelic"), String::from("chart-library")],
+                    ..Default::default()
+                }],
+            )]),
             ..Default::default()
         };
 
         assert_eq!(yaml, expected_output);
     }
+
+    #[test]
+    fn test_repo_index_round_trips() {
+        let original = r#"
+        apiVersion: v1
+        serverInfo:
+            contextPath: /v1/helm
+        annotations:
+            example.com/owner: platform
+        entries:
+            nri-bundle:
+                -   annotations:
+                        artifacthub.io/license: Apache-2.0
+                    apiVersion: v2
+                    appVersion: 1.0.0
+                    created: "2023-12-19T10:00:00.123456789Z"
+                    dependencies:
+                        -   condition: newrelic-infrastructure.enabled,infrastructure.enabled
+                            name: newrelic-infrastructure
+                            repository: https://newrelic.github.io/nri-kubernetes
+                            version: 3.28.0
+                        -   alias: kube-state-metrics
+                            import-values:
+                                -   child: service
+                                    parent: ksm.service
+                                - labels
+                            name: kube-state-metrics
+                            repository: https://prometheus-community.github.io/helm-charts
+                            tags: [metrics]
+                            version: ">=2.13 <6"
+                    description: Groups together the individual charts for the New Relic Kubernetes solution
+                    digest: 8d1c4d4f0d8a3f7b19c4b5d6e7f8a9b0c1d2e3f4a5b6c7d8e9f0a1b2c3d4e5f6
+                    home: https://github.com/newrelic/helm-charts
+                    icon: https://newrelic.com/themes/custom/curio/assets/mediakit/new_relic_logo_vertical.svg
+                    keywords: [infrastructure, newrelic, monitoring]
+                    kubeVersion: ">=1.21.0-0"
+                    maintainers:
+                        -   name: juanjjaramillo
+                            url: https://github.com/juanjjaramillo
+                        -   email: support@newrelic.com
+                            name: New Relic
+                    name: nri-bundle
+                    sources: [https://github.com/newrelic/helm-charts/tree/master/charts/nri-bundle]
+                    type: application
+                    urls: [https://github.com/newrelic/helm-charts/releases/download/nri-bundle-5.0.55/nri-bundle-5.0.55.tgz]
+                    version: 5.0.55
+                -   apiVersion: v1
+                    created: "2019-01-01T00:00:00Z"
+                    deprecated: true
+                    description: A chart from before Helm 3
+                    name: nri-bundle
+                    removed: true
+                    urls: [charts/nri-bundle-0.1.0.tgz]
+                    version: 0.1.0
+            rancher-monitoring:
+                -   apiVersion: v2
+                    appVersion: v0.59.1
+                    created: "2023-11-02T08:30:00Z"
+                    description: Collects several related Helm charts that provide easy to operate end-to-end Kubernetes cluster monitoring
+                    digest: 1f2e3d4c5b6a79881f2e3d4c5b6a79881f2e3d4c5b6a79881f2e3d4c5b6a7988
+                    name: rancher-monitoring
+                    urls: [assets/rancher-monitoring/rancher-monitoring-102.0.2+up40.1.2.tgz]
+                    version: 102.0.2+up40.1.2
+                -   apiVersion: v2
+                    created: "2023-10-02T08:30:00Z"
+                    name: rancher-monitoring
+                    urls: [assets/rancher-monitoring/rancher-monitoring-102.0.2-rc.1+up40.1.2.tgz]
+                    version: 102.0.2-rc.1+up40.1.2
+        generated: "2023-12-20T14:26:26.392635056Z"
+        publicKeys:
+            - keys/newrelic.asc
+        "#;
+
+        let index: Repository = serde_yaml::from_str(original).unwrap();
+        let written = serde_yaml::to_string(&index).unwrap();
+
+        let original: serde_yaml::Value = serde_yaml::from_str(original).unwrap();
+        let written: serde_yaml::Value = serde_yaml::from_str(&written).unwrap();
+        assert_eq!(written, original);
+    }
 }
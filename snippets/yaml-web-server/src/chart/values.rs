@@ -0,0 +1,410 @@
+// What Helm makes of a chart's dependencies given the values it is installed with: which
+// subcharts a condition or a tag turns off, the name an alias gives them, and the values
+// `import-values` lifts out of them into the parent, and how values scope down the tree: a
+// subchart gets the parent's key named after it over its own defaults, and `global` reaches
+// every level.
+use serde::Serialize;
+use serde_yaml::{Mapping, Value};
+
+use super::merger::coalesce;
+use super::spec::{Chart, Dependency, Version};
+
+const GLOBAL: &str = "global";
+
+// A chart, its default values and the charts its dependencies resolved to.
+#[derive(Debug, Clone)]
+pub struct Tree {
+    pub chart: Chart,
+    pub values: Value,
+    pub dependencies: Vec<(Dependency, Option<Tree>)>, // `None` when nothing satisfies it.
+}
+
+#[derive(Debug, Serialize)]
+pub struct Evaluation {
+    pub values: Value,
+    pub subcharts: Vec<Subchart>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Subchart {
+    pub path: String, // The subchart's name under each parent from the top, joined with dots.
+    pub chart: String,
+    pub version: Option<Version>,
+    pub enabled: bool,
+    pub reason: String,
+}
+
+pub fn evaluate(tree: &Tree, values: Value) -> Evaluation {
+    let mut warnings = Vec::new();
+    let mut subcharts = Vec::new();
+
+    // Conditions and tags see every subchart's defaults, whether it ends up enabled or not.
+    let all = coalesce_tree(tree, values.clone(), false, &mut warnings);
+    let enabled = select(tree, &all, "", &mut subcharts, &mut warnings);
+    let values = coalesce_tree(&enabled, values, true, &mut warnings);
+
+    Evaluation {
+        values,
+        subcharts,
+        warnings,
+    }
+}
+
+// The alias if there is one; it is also the key of the subchart's values in the parent.
+pub fn subchart_name(dependency: &Dependency) -> &str {
+    match dependency.alias.as_str() {
+        "" => &dependency.name,
+        alias => alias,
+    }
+}
+
+// The chart's values with each subchart's under its name. With `imports`, subcharts are settled
+// first so that what a parent imports already holds what they import themselves.
+fn coalesce_tree(tree: &Tree, supplied: Value, imports: bool, warnings: &mut Vec<String>) -> Value {
+    let mut values = coalesce(supplied, &tree.values);
+    let mut imported = Value::Mapping(Mapping::new());
+    let globals = values.get(GLOBAL).cloned().unwrap_or_default();
+
+    for (dependency, subchart) in &tree.dependencies {
+        let Some(subchart) = subchart else {
+            continue;
+        };
+        let name = subchart_name(dependency);
+        let Value::Mapping(parent) = &mut values else {
+            warnings.push(format!("{}: values are not a map", tree.chart.name));
+            break;
+        };
+        let section = parent.get(name).cloned().unwrap_or_default();
+        let section = with_globals(section, &globals, name, warnings);
+        let section = coalesce_tree(subchart, section, imports, warnings);
+
+        if imports {
+            for import in &dependency.import_values {
+                import_value(
+                    &tree.chart.name,
+                    name,
+                    import,
+                    &section,
+                    &mut imported,
+                    warnings,
+                );
+            }
+        }
+        parent.insert(Value::from(name), section);
+    }
+
+    // Imported values rank below the parent's own.
+    coalesce(values, &imported)
+}
+
+// The parent's globals laid over the subchart's own. The subchart's defaults go under both once
+// the section is coalesced with them, so `global` reads the same at every level.
+fn with_globals(section: Value, globals: &Value, name: &str, warnings: &mut Vec<String>) -> Value {
+    let mut section = match section {
+        Value::Null => Value::Mapping(Mapping::new()),
+        section => section,
+    };
+    let Value::Mapping(mapping) = &mut section else {
+        warnings.push(format!("{name}: values are not a map"));
+        return section;
+    };
+    let own = mapping.get(GLOBAL).cloned().unwrap_or_default();
+    if !own.is_null() && !own.is_mapping() {
+        warnings.push(format!("{name}.{GLOBAL} is not a map"));
+        return section;
+    }
+    let merged = match coalesce(globals.clone(), &own) {
+        Value::Null => Value::Mapping(Mapping::new()),
+        merged => merged,
+    };
+    mapping.insert(Value::from(GLOBAL), merged);
+    section
+}
+
+fn import_value(
+    parent: &str,
+    name: &str,
+    import: &Value,
+    section: &Value,
+    imported: &mut Value,
+    warnings: &mut Vec<String>,
+) {
+    let (child, target) = match import {
+        Value::String(export) => (format!("exports.{export}"), String::new()),
+        Value::Mapping(mapping) => {
+            let field = |key: &str| mapping.get(key).and_then(Value::as_str).unwrap_or_default();
+            (field("child").to_string(), field("parent").to_string())
+        }
+        _ => {
+            warnings.push(format!(
+                "{parent}: import-values of {name} must be strings or child/parent maps"
+            ));
+            return;
+        }
+    };
+
+    let Some(value) = lookup(section, &child) else {
+        warnings.push(format!("{parent}: {name} has no {child} to import"));
+        return;
+    };
+    let target = target.trim_matches('.');
+    if target.is_empty() && !value.is_mapping() {
+        warnings.push(format!(
+            "{parent}: {name}.{child} is not a map and cannot be imported at the top"
+        ));
+        return;
+    }
+    place(imported, target, value.clone());
+}
+
+// Drops disabled subcharts from the tree, and lists every subchart with why it is in or out.
+fn select(
+    tree: &Tree,
+    all: &Value,
+    prefix: &str,
+    subcharts: &mut Vec<Subchart>,
+    warnings: &mut Vec<String>,
+) -> Tree {
+    let mut dependencies = Vec::new();
+    for (dependency, subchart) in &tree.dependencies {
+        let path = format!("{prefix}{}", subchart_name(dependency));
+        let (enabled, reason) = enabled(dependency, all, prefix, warnings);
+        let version = subchart.as_ref().map(|s| s.chart.version.clone());
+        if enabled && subchart.is_none() {
+            warnings.push(format!(
+                "{path} is enabled but no {} matches {}",
+                dependency.name, dependency.version
+            ));
+        }
+
+        subcharts.push(Subchart {
+            path: path.clone(),
+            chart: dependency.name.clone(),
+            version,
+            enabled,
+            reason,
+        });
+        if let (true, Some(subchart)) = (enabled, subchart) {
+            let subtree = select(subchart, all, &format!("{path}."), subcharts, warnings);
+            dependencies.push((dependency.clone(), Some(subtree)));
+        }
+    }
+
+    Tree {
+        chart: tree.chart.clone(),
+        values: tree.values.clone(),
+        dependencies,
+    }
+}
+
+// Tags come from the top-level `tags` map: one true tag enables, otherwise a false one disables.
+// The first condition path that holds a boolean overrides them. Conditions are read relative to
+// the parent chart's place in the top-level values.
+fn enabled(
+    dependency: &Dependency,
+    all: &Value,
+    prefix: &str,
+    warnings: &mut Vec<String>,
+) -> (bool, String) {
+    let mut decision = (true, String::from("enabled by default"));
+
+    let tags = dependency
+        .tags
+        .iter()
+        .filter_map(|tag| Some((tag, lookup(all, &format!("tags.{tag}"))?.as_bool()?)));
+    let (on, off): (Vec<_>, Vec<_>) = tags.partition(|(_, on)| *on);
+    if let Some((tag, _)) = on.first() {
+        decision = (true, format!("tag {tag} is true"));
+    } else if let Some((tag, _)) = off.first() {
+        decision = (false, format!("tag {tag} is false"));
+    }
+
+    for condition in dependency.condition.split(',').map(str::trim) {
+        if condition.is_empty() {
+            continue;
+        }
+        let path = format!("{prefix}{condition}");
+        match lookup(all, &path) {
+            Some(Value::Bool(on)) => return (*on, format!("condition {path} is {on}")),
+            Some(_) => warnings.push(format!("condition {path} is not a boolean")),
+            None => {}
+        }
+    }
+    decision
+}
+
+pub fn lookup<'a>(values: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .filter(|key| !key.is_empty())
+        .try_fold(values, |value, key| value.get(key))
+}
+
+// Coalesces `value` in at `path`, making the maps on the way. What is already there wins.
+fn place(values: &mut Value, path: &str, value: Value) {
+    let nested = path
+        .rsplit('.')
+        .filter(|key| !key.is_empty())
+        .fold(value, |value, key| {
+            let mut mapping = Mapping::new();
+            mapping.insert(Value::from(key), value);
+            Value::Mapping(mapping)
+        });
+    *values = coalesce(std::mem::take(values), &nested);
+}
+
+#[cfg(test)]
+mod test {
+    use serde_yaml::Value;
+
+    use super::{evaluate, Subchart, Tree};
+    use crate::chart::spec::Chart;
+
+    fn tree(chart: &str, values: &str, dependencies: Vec<(&str, Option<Tree>)>) -> Tree {
+        let chart: Chart = serde_yaml::from_str(chart).unwrap();
+        let dependencies = chart
+            .dependencies
+            .iter()
+            .cloned()
+            .zip(dependencies)
+            .map(|(dependency, (name, subchart))| {
+                assert_eq!(dependency.name, name);
+                (dependency, subchart)
+            })
+            .collect();
+        Tree {
+            chart,
+            values: serde_yaml::from_str(values).unwrap(),
+            dependencies,
+        }
+    }
+
+    #[test]
+    fn test_conditions_tags_aliases_and_imports() {
+        let postgresql = || {
+            tree(
+                "apiVersion: v2\nname: postgresql\nversion: 12.1.0\n",
+                "enabled: true\nport: 5432\nexports:\n  connection:\n    dbPort: 5432\n",
+                vec![],
+            )
+        };
+        let redis = tree(
+            "apiVersion: v2\nname: redis\nversion: 17.3.0\n",
+            "auth: {enabled: true}\nmetrics: {enabled: false}\n",
+            vec![],
+        );
+        let app = tree(
+            r#"
+apiVersion: v2
+name: app
+version: 1.0.0
+dependencies:
+  - name: postgresql
+    version: ~12.1.0
+    alias: primary
+    condition: primary.enabled
+    import-values:
+      - connection
+      - child: port
+        parent: database.port
+  - name: postgresql
+    version: ~12.1.0
+    alias: replica
+    condition: replica.enabled,global.replicas
+  - name: redis
+    version: 17.x
+    tags: [cache]
+  - name: memcached
+    version: 6.x
+    tags: [cache, legacy]
+"#,
+            "dbPort: 6543\nreplica: {enabled: false}\ntags: {legacy: false}\n",
+            vec![
+                ("postgresql", Some(postgresql())),
+                ("postgresql", Some(postgresql())),
+                ("redis", Some(redis)),
+                ("memcached", None),
+            ],
+        );
+
+        let user: Value =
+            serde_yaml::from_str("tags: {cache: false}\nredis: {auth: {enabled: false}}\n")
+                .unwrap();
+        let evaluation = evaluate(&app, user);
+
+        let summary: Vec<(&str, bool, &str)> = evaluation
+            .subcharts
+            .iter()
+            .map(|s: &Subchart| (s.path.as_str(), s.enabled, s.reason.as_str()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("primary", true, "condition primary.enabled is true"),
+                ("replica", false, "condition replica.enabled is false"),
+                ("redis", false, "tag cache is false"),
+                ("memcached", false, "tag cache is false"),
+            ]
+        );
+
+        let expected: Value = serde_yaml::from_str(
+            r#"
+dbPort: 6543
+replica: {enabled: false}
+tags: {legacy: false, cache: false}
+redis: {auth: {enabled: false}}
+primary:
+  global: {}
+  enabled: true
+  port: 5432
+  exports: {connection: {dbPort: 5432}}
+database: {port: 5432}
+"#,
+        )
+        .unwrap();
+        assert_eq!(evaluation.values, expected);
+        assert!(evaluation.warnings.is_empty(), "{:?}", evaluation.warnings);
+    }
+
+    #[test]
+    fn test_globals_reach_every_level() {
+        let exporter = tree(
+            "apiVersion: v2\nname: exporter\nversion: 0.1.0\n",
+            "global: {imageRegistry: docker.io, labels: {component: exporter}}\n",
+            vec![],
+        );
+        let database = tree(
+            "apiVersion: v2\nname: database\nversion: 1.0.0\ndependencies:\n  - name: exporter\n    version: 0.x\n",
+            "global: {labels: {tier: data}}\nreplicas: 1\n",
+            vec![("exporter", Some(exporter))],
+        );
+        let app = tree(
+            "apiVersion: v2\nname: app\nversion: 1.0.0\ndependencies:\n  - name: database\n    version: 1.x\n    alias: db\n",
+            "global: {imageRegistry: registry.example.com}\ndb: {replicas: 2}\n",
+            vec![("database", Some(database))],
+        );
+
+        let user: Value = serde_yaml::from_str("global: {labels: {team: payments}}\n").unwrap();
+        let evaluation = evaluate(&app, user);
+
+        let expected: Value = serde_yaml::from_str(
+            r#"
+global:
+  labels: {team: payments}
+  imageRegistry: registry.example.com
+db:
+  replicas: 2
+  global:
+    labels: {team: payments, tier: data}
+    imageRegistry: registry.example.com
+  exporter:
+    global:
+      labels: {team: payments, tier: data, component: exporter}
+      imageRegistry: registry.example.com
+"#,
+        )
+        .unwrap();
+        assert_eq!(evaluation.values, expected);
+    }
+}
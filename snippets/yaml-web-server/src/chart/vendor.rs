@@ -0,0 +1,63 @@
+// `dependency build`: the archives a chart depends on, written into its `charts/` folder so that
+// it packages and evaluates without reaching a repository.
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::package::NamedArchive;
+
+pub const CHARTS_DIR: &str = "charts";
+
+// Like Helm, every other archive in `charts/` goes, so a dependency dropped from Chart.yaml or
+// moved to another version does not linger. Unpacked subcharts are left where they are.
+pub fn vendor(chart_dir: &Path, archives: &[NamedArchive]) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let dir = chart_dir.join(CHARTS_DIR);
+    fs::create_dir_all(&dir)?;
+
+    let mut written = Vec::new();
+    for (file_name, archive) in archives {
+        let path = dir.join(file_name);
+        fs::write(&path, archive)?;
+        written.push(path);
+    }
+
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        let archive = path.extension().is_some_and(|e| e == "tgz");
+        if archive && path.is_file() && !written.contains(&path) {
+            fs::remove_file(&path)?;
+        }
+    }
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::vendor;
+
+    #[test]
+    fn test_vendor_replaces_stale_archives() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        fs::create_dir_all(dir.join("charts/unpacked")).unwrap();
+        fs::write(dir.join("charts/common-1.0.0.tgz"), b"old").unwrap();
+        fs::write(dir.join("charts/dropped-0.1.0.tgz"), b"old").unwrap();
+
+        let written = vendor(&dir, &[(String::from("common-1.1.0.tgz"), b"new".to_vec())]).unwrap();
+        assert_eq!(written, [dir.join("charts/common-1.1.0.tgz")]);
+
+        let mut left: Vec<String> = fs::read_dir(dir.join("charts"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["common-1.1.0.tgz", "unpacked"]);
+        assert_eq!(
+            fs::read(dir.join("charts/common-1.1.0.tgz")).unwrap(),
+            b"new"
+        );
+    }
+}
@@ -0,0 +1,194 @@
+// Commands that work on the chart folders directly and exit, instead of serving them.
+use std::error::Error;
+use std::path::PathBuf;
+
+use reqwest::Client;
+
+use crate::catalog::{self, merge_charts, ChartPackage, LoadError};
+use crate::chart::spec::Chart;
+use crate::chart::{bump, vendor};
+use crate::config::{ChartsConfig, Command, Config, DependencyCommand, DEFAULT_REPO};
+use crate::server::repo::Repo;
+
+pub async fn run(command: Command, config: &Config) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::Bump {
+            chart,
+            part,
+            app_version,
+            update_dependents,
+            repo,
+        } => {
+            let charts = load_charts(config, &repo).await?;
+            let report = bump::bump(
+                charts.iter().map(|(chart, path)| (chart, path.as_path())),
+                &repo_config(config, &repo)?.overrides,
+                &chart,
+                part,
+                app_version.as_deref(),
+                update_dependents,
+            )?;
+
+            println!(
+                "{} {} -> {} in {}",
+                report.chart,
+                report.from,
+                report.to,
+                report.descriptor.display()
+            );
+            for dependent in &report.dependents {
+                let outcome = match (&dependent.proposed, dependent.updated) {
+                    (Some(proposed), true) => format!("updated to {proposed}"),
+                    (Some(proposed), false) if *proposed == dependent.constraint => {
+                        String::from("already takes the new version")
+                    }
+                    (Some(proposed), false) if update_dependents => {
+                        format!("not updated, set {proposed} where its dependency is declared")
+                    }
+                    (Some(proposed), false) => {
+                        format!("would become {proposed} with --update-dependents")
+                    }
+                    (None, _) => String::from("needs to be updated by hand"),
+                };
+                println!(
+                    "  {} ({}) depends on {}: {outcome}",
+                    dependent.chart, dependent.constraint, report.chart
+                );
+            }
+            Ok(())
+        }
+        Command::Dependency(DependencyCommand::Build { charts, repo }) => {
+            dependency_build(config, &repo, &charts).await
+        }
+        Command::Show {
+            part,
+            chart,
+            version,
+            json,
+            repo,
+        } => {
+            let http = Client::new();
+            let repo = load_repo(config, &repo, &http).await?;
+            let Some(shown) = repo.show(&http, &chart, version.as_ref()).await? else {
+                return match version {
+                    Some(version) => Err(format!("chart {chart} {version} does not exist").into()),
+                    None => Err(format!("chart {chart} does not exist").into()),
+                };
+            };
+            if json {
+                println!("{}", serde_json::to_string_pretty(&shown.json(part)?)?);
+            } else {
+                print!("{}", shown.yaml(part)?);
+            }
+            Ok(())
+        }
+        Command::Lint { repo } => {
+            let report = merge_charts(repo_config(config, &repo)?, None).await;
+            let problems: Vec<LoadError> = report
+                .errors
+                .into_iter()
+                .chain(report.packages.iter().flat_map(ChartPackage::lint))
+                .collect();
+            for problem in &problems {
+                println!("{problem}");
+            }
+            match problems.len() {
+                0 => Ok(()),
+                n => Err(format!("{n} problem(s) found in {repo}").into()),
+            }
+        }
+    }
+}
+
+// Loads the repository as the server would, upstreams included, and vendors what each chart
+// needs. A chart whose dependencies cannot all be found is left as it was.
+async fn dependency_build(
+    config: &Config,
+    repo: &str,
+    names: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let http = Client::new();
+    let repo = load_repo(config, repo, &http).await?;
+
+    let mut charts: Vec<(Chart, PathBuf)> = repo
+        .catalog
+        .read()
+        .map_err(|e| e.to_string())?
+        .packages
+        .iter()
+        .filter_map(|p| Some((p.chart.clone(), p.descriptor.clone()?)))
+        .filter(|(chart, _)| names.is_empty() || names.contains(&chart.name))
+        .collect();
+    if let Some(missing) = names
+        .iter()
+        .find(|n| !charts.iter().any(|(c, _)| c.name == **n))
+    {
+        return Err(format!("{missing} is not a chart from a chart root").into());
+    }
+    charts.retain(|(chart, _)| !chart.dependencies.is_empty());
+
+    let mut failed = 0;
+    for (chart, descriptor) in &charts {
+        let chart_dir = descriptor
+            .parent()
+            .ok_or("chart descriptor has no parent folder")?;
+        let vendored = match repo.dependency_archives(&http, chart).await {
+            Ok(archives) => vendor::vendor(chart_dir, &archives).map_err(|e| e.to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+        match vendored {
+            Ok(written) => {
+                for path in written {
+                    println!("{}: {}", chart.name, path.display());
+                }
+            }
+            Err(err) => {
+                eprintln!("{}: {err}", chart.name);
+                failed += 1;
+            }
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        n => Err(format!("{n} chart(s) could not be built").into()),
+    }
+}
+
+// The repository loaded as the server would, upstreams included.
+async fn load_repo(config: &Config, name: &str, http: &Client) -> Result<Repo, Box<dyn Error>> {
+    let base_url = config.server.base_url.clone();
+    let repo = Repo::new(name, repo_config(config, name)?.clone(), base_url);
+    let report = catalog::load(&repo.charts, None, http).await;
+    for err in &report.errors {
+        eprintln!("could not load chart in {name}: {err}");
+    }
+    repo.catalog
+        .write()
+        .map_err(|e| e.to_string())?
+        .replace(report.packages, report.mirrored);
+    Ok(repo)
+}
+
+fn repo_config<'a>(config: &'a Config, repo: &str) -> Result<&'a ChartsConfig, String> {
+    match repo {
+        DEFAULT_REPO => Ok(&config.charts),
+        name => config
+            .repos
+            .get(name)
+            .ok_or_else(|| format!("repository {name} does not exist")),
+    }
+}
+
+// The charts of a repository that come from a chart root, with the descriptor of each.
+async fn load_charts(config: &Config, repo: &str) -> Result<Vec<(Chart, PathBuf)>, Box<dyn Error>> {
+    let report = merge_charts(repo_config(config, repo)?, None).await;
+    for err in &report.errors {
+        eprintln!("could not load chart in {repo}: {err}");
+    }
+    Ok(report
+        .packages
+        .into_iter()
+        .filter_map(|p| Some((p.chart, p.descriptor?)))
+        .collect())
+}
@@ -0,0 +1,670 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use serde::Deserialize;
+
+use crate::chart::artifacthub::RepositoryMetadata;
+use crate::chart::show::Part;
+use crate::chart::spec::{Bump, Version};
+
+const DEFAULT_CONFIG_FILE: &str = "yaml-web-server.yaml";
+pub const DEFAULT_REPO: &str = "default";
+
+// Precedence is CLI flags > environment variables > config file > defaults. Clap already resolves
+// the first two, so every field here is optional and only overrides the file when it is set.
+#[derive(Debug, Default, Parser)]
+#[command(
+    version,
+    about = "Serves a Helm chart repository out of local chart folders"
+)]
+pub struct Args {
+    #[arg(short, long, env = "YWS_CONFIG")]
+    pub config: Option<PathBuf>,
+    #[arg(long = "chart-root", env = "YWS_CHART_ROOTS", value_delimiter = ',')]
+    pub chart_roots: Vec<PathBuf>,
+    #[arg(long = "override", env = "YWS_OVERRIDES", value_delimiter = ',')]
+    pub overrides: Vec<PathBuf>,
+    #[arg(long, env = "YWS_DESCRIPTOR_FILE")]
+    pub descriptor_file: Option<String>,
+    #[arg(long, env = "YWS_UPLOADS")]
+    pub uploads: Option<PathBuf>,
+    #[arg(long, env = "YWS_RELOAD_INTERVAL")]
+    pub reload_interval: Option<u64>,
+    #[arg(long, env = "YWS_BIND", value_delimiter = ',')]
+    pub bind: Vec<SocketAddr>,
+    #[arg(long, env = "YWS_BASE_URL")]
+    pub base_url: Option<String>,
+    #[arg(long, env = "YWS_TLS_CERT")]
+    pub tls_cert: Option<PathBuf>,
+    #[arg(long, env = "YWS_TLS_KEY")]
+    pub tls_key: Option<PathBuf>,
+    #[arg(long, env = "YWS_TLS_CLIENT_CA")]
+    pub tls_client_ca: Option<PathBuf>,
+    #[arg(long = "auth-token", env = "YWS_AUTH_TOKENS", value_delimiter = ',')]
+    pub auth_tokens: Vec<String>,
+    #[arg(long, env = "YWS_HTPASSWD_FILE")]
+    pub htpasswd_file: Option<PathBuf>,
+    #[arg(long, env = "YWS_JWT_KEY_FILE")]
+    pub jwt_key_file: Option<PathBuf>,
+    #[arg(long, env = "YWS_ALLOW_ANONYMOUS_WRITES")]
+    pub allow_anonymous_writes: Option<bool>,
+    #[arg(long, env = "YWS_CACHE_MAX_AGE")]
+    pub cache_max_age: Option<u64>,
+    #[arg(long, env = "YWS_SIGNING_KEY")]
+    pub signing_key: Option<PathBuf>,
+    #[arg(long, env = "YWS_KEYRING")]
+    pub keyring: Option<PathBuf>,
+    #[arg(long, env = "YWS_REQUIRE_PROVENANCE")]
+    pub require_provenance: Option<bool>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+// One-off tasks on the configured chart folders; without one the server runs.
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    #[command(about = "Bumps a chart's version and lists, or updates, the charts depending on it")]
+    Bump {
+        chart: String,
+        #[arg(value_name = "major|minor|patch|prerelease")]
+        part: Bump,
+        #[arg(long)]
+        app_version: Option<String>,
+        #[arg(long)]
+        update_dependents: bool,
+        #[arg(long, default_value = DEFAULT_REPO)]
+        repo: String,
+    },
+    #[command(
+        about = "Loads a repository's charts and reports every problem, annotations included"
+    )]
+    Lint {
+        #[arg(long, default_value = DEFAULT_REPO)]
+        repo: String,
+    },
+    #[command(
+        about = "Shows a served chart's Chart.yaml, values, README or CRDs, like `helm show`"
+    )]
+    Show {
+        #[arg(value_name = "chart|values|readme|crds|all")]
+        part: Part,
+        chart: String,
+        #[arg(long)]
+        version: Option<Version>, // The newest served version when missing.
+        #[arg(long)]
+        json: bool,
+        #[arg(long, default_value = DEFAULT_REPO)]
+        repo: String,
+    },
+    #[command(
+        subcommand,
+        about = "Works on the dependencies of charts in the chart roots"
+    )]
+    Dependency(DependencyCommand),
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DependencyCommand {
+    #[command(about = "Vendors the charts each chart depends on into its charts/ folder")]
+    Build {
+        charts: Vec<String>, // Every chart from the chart roots when none is given.
+        #[arg(long, default_value = DEFAULT_REPO)]
+        repo: String,
+    },
+}
+
+#[derive(Debug, Deserialize, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub charts: ChartsConfig, // The default repository, served at the root.
+    // Extra repositories served under /repos/<name>, each loaded and reloaded on its own.
+    pub repos: BTreeMap<String, ChartsConfig>,
+    pub server: ServerConfig,
+    pub auth: AuthConfig,
+    pub cache: CacheConfig,
+    pub provenance: ProvenanceConfig,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChartsConfig {
+    pub roots: Vec<PathBuf>,
+    #[serde(rename = "descriptorFile")]
+    pub descriptor_file: String,
+    // Applied in order, so a later folder wins over an earlier one.
+    pub overrides: Vec<PathBuf>,
+    pub uploads: PathBuf, // Where charts pushed through the API are stored.
+    #[serde(rename = "reloadInterval")]
+    pub reload_interval: u64, // Seconds between catalog reloads, 0 disables them.
+    pub upstreams: Vec<UpstreamConfig>, // Remote repositories mirrored into this one.
+    // Which source wins when several offer the same chart version, highest first. Sources left
+    // out rank below the listed ones, in the default order.
+    pub priority: Vec<Source>,
+    // Served as artifacthub-repo.yml; without it Artifact Hub still indexes the repository.
+    #[serde(rename = "artifactHub")]
+    pub artifact_hub: Option<RepositoryMetadata>,
+}
+
+// Where the charts of a repository come from.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    Local,     // Packaged from the chart roots.
+    Uploads,   // Pushed through the API.
+    Upstreams, // Mirrored, each upstream ranked in the order it is configured.
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let source = match self {
+            Source::Local => "local",
+            Source::Uploads => "uploads",
+            Source::Upstreams => "upstreams",
+        };
+        f.write_str(source)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct UpstreamConfig {
+    pub url: String,    // Where the upstream index.yaml lives, without the file name.
+    pub cache: PathBuf, // Keeps the last index and every archive fetched from the upstream.
+    pub timeout: u64,   // Seconds to wait on the upstream before falling back to the cache.
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: Vec<SocketAddr>,
+    #[serde(rename = "baseUrl")]
+    pub base_url: String,
+    pub tls: TlsConfig,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    #[serde(rename = "certFile")]
+    pub cert_file: Option<PathBuf>, // PEM chain, leaf first. Setting it turns TLS on.
+    #[serde(rename = "keyFile")]
+    pub key_file: Option<PathBuf>,
+    #[serde(rename = "clientCaFile")]
+    pub client_ca_file: Option<PathBuf>, // Clients must present a certificate signed by one of these.
+}
+
+impl TlsConfig {
+    pub fn enabled(&self) -> bool {
+        self.cert_file.is_some()
+    }
+}
+
+#[derive(Debug, Deserialize, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    #[serde(rename = "bearerTokens")]
+    pub bearer_tokens: Vec<String>,
+    #[serde(rename = "htpasswdFile")]
+    pub htpasswd_file: Option<PathBuf>,
+    #[serde(rename = "jwtKeyFile")]
+    pub jwt_key_file: Option<PathBuf>, // HMAC secret, or an RSA/EC public key in PEM.
+    // Chart-name prefixes each htpasswd user or JWT subject may publish under. Subjects that are
+    // not listed may publish any chart.
+    pub scopes: BTreeMap<String, Vec<String>>,
+    // Lets callers without credentials upload, delete, edit overrides and bump. Off by default.
+    #[serde(rename = "allowAnonymousWrites")]
+    pub allow_anonymous_writes: bool,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    #[serde(rename = "maxAge")]
+    pub max_age: u64, // Seconds clients may cache index.yaml for.
+}
+
+#[derive(Debug, Deserialize, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProvenanceConfig {
+    #[serde(rename = "signingKey")]
+    pub signing_key: Option<PathBuf>, // Unprotected OpenPGP secret key used to sign packaged charts.
+    pub keyring: Option<PathBuf>, // Public keys trusted to sign uploaded charts.
+    pub required: bool,           // Reject uploads that do not carry a verified .prov file.
+}
+
+impl ChartsConfig {
+    fn validate(&self) -> Result<(), String> {
+        // A pure mirror has nothing local to serve.
+        if self.roots.is_empty() && self.upstreams.is_empty() {
+            return Err(String::from(
+                "at least one chart root or upstream is required",
+            ));
+        }
+        for root in &self.roots {
+            if !root.is_dir() {
+                return Err(format!("chart root {} is not a directory", root.display()));
+            }
+        }
+        for override_ in &self.overrides {
+            if !override_.is_dir() {
+                return Err(format!(
+                    "override folder {} is not a directory",
+                    override_.display()
+                ));
+            }
+        }
+
+        if self.uploads.exists() && !self.uploads.is_dir() {
+            return Err(format!(
+                "uploads folder {} is not a directory",
+                self.uploads.display()
+            ));
+        }
+
+        for upstream in &self.upstreams {
+            if !upstream.url.starts_with("http://") && !upstream.url.starts_with("https://") {
+                return Err(format!(
+                    "upstream URL `{}` must start with http:// or https://",
+                    upstream.url
+                ));
+            }
+            if upstream.cache.as_os_str().is_empty() {
+                return Err(format!("upstream {} needs a cache folder", upstream.url));
+            }
+        }
+
+        for (i, source) in self.priority.iter().enumerate() {
+            if self.priority[..i].contains(source) {
+                return Err(format!("source {source} is listed twice in the priority"));
+            }
+        }
+
+        if let Some(artifact_hub) = &self.artifact_hub {
+            artifact_hub.validate()?;
+        }
+
+        let descriptor_file = &self.descriptor_file;
+        if descriptor_file.is_empty() || descriptor_file.contains(['/', '\\']) {
+            return Err(format!(
+                "descriptor file `{descriptor_file}` must be a plain file name"
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn sources(&self) -> Vec<Source> {
+        let mut sources = self.priority.clone();
+        for source in [Source::Local, Source::Uploads, Source::Upstreams] {
+            if !sources.contains(&source) {
+                sources.push(source);
+            }
+        }
+        sources
+    }
+}
+
+impl Default for ChartsConfig {
+    fn default() -> Self {
+        ChartsConfig {
+            roots: vec![PathBuf::from("charts")],
+            descriptor_file: String::from("Chart.yaml"),
+            overrides: vec![PathBuf::from("local")],
+            uploads: PathBuf::from("uploads"),
+            reload_interval: 0,
+            upstreams: Vec::new(),
+            priority: Vec::new(),
+            artifact_hub: None,
+        }
+    }
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        UpstreamConfig {
+            url: String::new(),
+            cache: PathBuf::new(),
+            timeout: 10,
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: vec![SocketAddr::from(([0, 0, 0, 0], 3000))],
+            base_url: String::from("http://localhost:3000"),
+            tls: TlsConfig::default(),
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig { max_age: 300 }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, serde_yaml::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, err) => {
+                write!(f, "cannot read config file {}: {err}", path.display())
+            }
+            ConfigError::Parse(path, err) => {
+                write!(f, "cannot parse config file {}: {err}", path.display())
+            }
+            ConfigError::Invalid(msg) => write!(f, "invalid configuration: {msg}"),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl Config {
+    pub fn load(args: Args) -> Result<Config, ConfigError> {
+        let mut config = match &args.config {
+            Some(path) => Config::from_file(path.clone())?,
+            None if PathBuf::from(DEFAULT_CONFIG_FILE).is_file() => {
+                Config::from_file(PathBuf::from(DEFAULT_CONFIG_FILE))?
+            }
+            None => Config::default(),
+        };
+
+        config.apply(args);
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn from_file(path: PathBuf) -> Result<Config, ConfigError> {
+        let file = File::open(&path).map_err(|e| ConfigError::Read(path.clone(), e))?;
+        serde_yaml::from_reader(file).map_err(|e| ConfigError::Parse(path, e))
+    }
+
+    fn apply(&mut self, args: Args) {
+        if !args.chart_roots.is_empty() {
+            self.charts.roots = args.chart_roots;
+        }
+        if !args.overrides.is_empty() {
+            self.charts.overrides = args.overrides;
+        }
+        if let Some(descriptor_file) = args.descriptor_file {
+            self.charts.descriptor_file = descriptor_file;
+        }
+        if let Some(uploads) = args.uploads {
+            self.charts.uploads = uploads;
+        }
+        if let Some(reload_interval) = args.reload_interval {
+            self.charts.reload_interval = reload_interval;
+        }
+        if !args.bind.is_empty() {
+            self.server.bind = args.bind;
+        }
+        if let Some(base_url) = args.base_url {
+            self.server.base_url = base_url;
+        }
+        if let Some(cert) = args.tls_cert {
+            self.server.tls.cert_file = Some(cert);
+        }
+        if let Some(key) = args.tls_key {
+            self.server.tls.key_file = Some(key);
+        }
+        if let Some(client_ca) = args.tls_client_ca {
+            self.server.tls.client_ca_file = Some(client_ca);
+        }
+        if !args.auth_tokens.is_empty() {
+            self.auth.bearer_tokens = args.auth_tokens;
+        }
+        if let Some(htpasswd_file) = args.htpasswd_file {
+            self.auth.htpasswd_file = Some(htpasswd_file);
+        }
+        if let Some(jwt_key_file) = args.jwt_key_file {
+            self.auth.jwt_key_file = Some(jwt_key_file);
+        }
+        if let Some(allow) = args.allow_anonymous_writes {
+            self.auth.allow_anonymous_writes = allow;
+        }
+        if let Some(max_age) = args.cache_max_age {
+            self.cache.max_age = max_age;
+        }
+        if let Some(signing_key) = args.signing_key {
+            self.provenance.signing_key = Some(signing_key);
+        }
+        if let Some(keyring) = args.keyring {
+            self.provenance.keyring = Some(keyring);
+        }
+        if let Some(required) = args.require_provenance {
+            self.provenance.required = required;
+        }
+    }
+
+    pub fn validate(&mut self) -> Result<(), ConfigError> {
+        self.charts.validate().map_err(ConfigError::Invalid)?;
+        let mut uploads = vec![&self.charts.uploads];
+        for (name, repo) in &self.repos {
+            let valid_name = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+            if !valid_name || name == DEFAULT_REPO {
+                return Err(ConfigError::Invalid(format!(
+                    "`{name}` cannot be used as a repository name"
+                )));
+            }
+            repo.validate()
+                .map_err(|err| ConfigError::Invalid(format!("repos.{name}: {err}")))?;
+
+            if uploads.contains(&&repo.uploads) {
+                return Err(ConfigError::Invalid(format!(
+                    "repos.{name}: uploads folder {} is already used by another repository",
+                    repo.uploads.display()
+                )));
+            }
+            uploads.push(&repo.uploads);
+        }
+
+        if self.server.bind.is_empty() {
+            return Err(ConfigError::Invalid(String::from(
+                "at least one bind address is required",
+            )));
+        }
+
+        let base_url = self.server.base_url.trim_end_matches('/');
+        if !base_url.starts_with("http://") && !base_url.starts_with("https://") {
+            return Err(ConfigError::Invalid(format!(
+                "base URL `{base_url}` must start with http:// or https://"
+            )));
+        }
+        self.server.base_url = base_url.to_string();
+
+        let tls = &self.server.tls;
+        if tls.cert_file.is_some() != tls.key_file.is_some() {
+            return Err(ConfigError::Invalid(String::from(
+                "server.tls needs both certFile and keyFile",
+            )));
+        }
+        if tls.client_ca_file.is_some() && !tls.enabled() {
+            return Err(ConfigError::Invalid(String::from(
+                "server.tls.clientCaFile needs certFile and keyFile",
+            )));
+        }
+        for file in [&tls.cert_file, &tls.key_file, &tls.client_ca_file]
+            .into_iter()
+            .flatten()
+        {
+            if !file.is_file() {
+                return Err(ConfigError::Invalid(format!(
+                    "TLS file {} does not exist",
+                    file.display()
+                )));
+            }
+        }
+
+        if self.auth.bearer_tokens.iter().any(|t| t.trim().is_empty()) {
+            return Err(ConfigError::Invalid(String::from(
+                "bearer tokens cannot be empty",
+            )));
+        }
+        for file in [&self.auth.htpasswd_file, &self.auth.jwt_key_file]
+            .into_iter()
+            .flatten()
+        {
+            if !file.is_file() {
+                return Err(ConfigError::Invalid(format!(
+                    "auth file {} does not exist",
+                    file.display()
+                )));
+            }
+        }
+
+        for file in [&self.provenance.signing_key, &self.provenance.keyring]
+            .into_iter()
+            .flatten()
+        {
+            if !file.is_file() {
+                return Err(ConfigError::Invalid(format!(
+                    "provenance file {} does not exist",
+                    file.display()
+                )));
+            }
+        }
+        if self.provenance.required && self.provenance.keyring.is_none() {
+            return Err(ConfigError::Invalid(String::from(
+                "provenance.required needs a keyring to verify uploads against",
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use std::path::PathBuf;
+
+    use clap::Parser;
+
+    use super::{Args, Config, ConfigError, Source};
+
+    #[test]
+    fn test_cli_flags_override_config_file() {
+        let mut config: Config = serde_yaml::from_str(
+            r#"
+        charts:
+            roots: [charts]
+            overrides: []
+        server:
+            bind: ["127.0.0.1:8080"]
+            baseUrl: https://charts.example.com/
+        "#,
+        )
+        .unwrap();
+
+        config.apply(Args::parse_from([
+            "yaml-web-server",
+            "--override",
+            "local",
+            "--bind",
+            "127.0.0.1:9090,[::1]:9090",
+        ]));
+        config.validate().unwrap();
+
+        assert_eq!(config.charts.roots, vec![PathBuf::from("charts")]);
+        assert_eq!(config.charts.overrides, vec![PathBuf::from("local")]);
+        assert_eq!(
+            config.server.bind,
+            vec![
+                "127.0.0.1:9090".parse::<SocketAddr>().unwrap(),
+                "[::1]:9090".parse::<SocketAddr>().unwrap(),
+            ]
+        );
+        assert_eq!(config.server.base_url, "https://charts.example.com");
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        let err = serde_yaml::from_str::<Config>("charts:\n  root: charts\n").unwrap_err();
+
+        assert!(err.to_string().contains("unknown field `root`"));
+    }
+
+    #[test]
+    fn test_invalid_config_is_reported() {
+        let mut config = Config::default();
+        config.server.base_url = String::from("charts.example.com");
+
+        match config.validate() {
+            Err(ConfigError::Invalid(msg)) => assert!(msg.contains("base URL")),
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_tls_needs_cert_and_key() {
+        let mut config = Config::default();
+        config.apply(Args::parse_from([
+            "yaml-web-server",
+            "--tls-cert",
+            "Cargo.toml",
+        ]));
+
+        match config.validate() {
+            Err(ConfigError::Invalid(msg)) => assert!(msg.contains("certFile and keyFile")),
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_repositories_need_their_own_uploads() {
+        let mut config: Config = serde_yaml::from_str(
+            r#"
+        repos:
+            stable:
+                roots: [charts]
+            incubator:
+                roots: [charts]
+                uploads: incubator-uploads
+        "#,
+        )
+        .unwrap();
+
+        match config.validate() {
+            Err(ConfigError::Invalid(msg)) => {
+                assert!(msg.contains("repos.stable: uploads folder uploads is already used"))
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_source_priority() {
+        let config: Config = serde_yaml::from_str("charts:\n  priority: [upstreams]\n").unwrap();
+        assert_eq!(
+            config.charts.sources(),
+            vec![Source::Upstreams, Source::Local, Source::Uploads]
+        );
+
+        let mut config: Config =
+            serde_yaml::from_str("charts:\n  priority: [uploads, local, uploads]\n").unwrap();
+        match config.validate() {
+            Err(ConfigError::Invalid(msg)) => {
+                assert!(msg.contains("source uploads is listed twice"))
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+}
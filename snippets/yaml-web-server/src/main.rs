@@ -1,35 +1,147 @@
-use axum::{routing::get, Router};
-use chart::{merger, spec::Chart};
-use glob::glob;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
 
-pub mod chart;
+use auth::Auth;
+use axum_server::tls_rustls::RustlsConfig;
+use clap::Parser;
+use config::{Args, Config};
+use metrics::Metrics;
+use pgp::{Keyring, SecretKey};
+use tokio::task::JoinSet;
 
-const CHART_FOLDER: &str = "charts";
-const CHART_DESCRIPTOR_FILE: &str = "Chart.yaml";
-const OVERRIDE_FOLDER: &str = "local";
+pub mod auth;
+pub mod catalog;
+pub mod chart;
+pub mod cli;
+pub mod config;
+pub mod metrics;
+pub mod mirror;
+pub mod pgp;
+pub mod server;
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    //let router = Router::new().route("/", get(say_hello_text));
-    //let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    //axum::serve(listener, router).await.unwrap();
+async fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = Args::parse();
+    let command = args.command.take();
+    let config = match Config::load(args) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}");
+            std::process::exit(2);
+        }
+    };
+
+    if let Some(command) = command {
+        if let Err(err) = cli::run(command, &config).await {
+            eprintln!("{err}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let (signer, keyring) = match load_keys(&config) {
+        Ok(keys) => keys,
+        Err(err) => {
+            eprintln!("invalid provenance keys: {err}");
+            std::process::exit(2);
+        }
+    };
+
+    let auth = match Auth::from_config(&config.auth) {
+        Ok(auth) => auth,
+        Err(err) => {
+            eprintln!("invalid auth settings: {err}");
+            std::process::exit(2);
+        }
+    };
+
+    let state = Arc::new(server::AppState::new(
+        config,
+        signer,
+        keyring,
+        auth,
+        Metrics::new()?,
+    ));
+    // Charts load in the background so liveness answers straight away; readiness tells when the
+    // catalog is actually there.
+    for name in state.repos.keys() {
+        tokio::spawn(load_catalog(state.clone(), name.clone()));
+    }
+
+    let router = server::router(state.clone());
+
+    let tls = &state.config.server.tls;
+    let rustls_config = if tls.enabled() {
+        match server::tls::server_config(tls) {
+            Ok(server_config) => Some(RustlsConfig::from_config(Arc::new(server_config))),
+            Err(err) => {
+                eprintln!("invalid TLS settings: {err}");
+                std::process::exit(2);
+            }
+        }
+    } else {
+        None
+    };
+    if let Some(rustls_config) = &rustls_config {
+        tokio::spawn(server::tls::watch(rustls_config.clone(), tls.clone()));
+    }
+
+    let mut servers = JoinSet::new();
+    for addr in &state.config.server.bind {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        let router = router.clone();
+        match &rustls_config {
+            Some(rustls_config) => {
+                println!("listening on {addr} (TLS)");
+                let server =
+                    axum_server::from_tcp_rustls(listener.into_std()?, rustls_config.clone());
+                servers.spawn(async move { server.serve(router.into_make_service()).await });
+            }
+            None => {
+                println!("listening on {addr}");
+                servers.spawn(async move { axum::serve(listener, router).await });
+            }
+        }
+    }
+
+    while let Some(result) = servers.join_next().await {
+        result??;
+    }
 
     Ok(())
 }
 
-async fn merge_charts() -> Vec<Chart> {
-    let paths = glob(format!("./{CHART_FOLDER}/*/{CHART_DESCRIPTOR_FILE}").as_str())
-        .expect("Failed to read glob pattern");
-    let mut charts: Vec<Chart> = Vec::new();
+// Loads a repository once, then again every `reloadInterval` seconds when that is set.
+async fn load_catalog(state: Arc<server::AppState>, name: String) {
+    let repo = &state.repos[&name];
+    let every = repo.charts.reload_interval;
+    let mut interval = tokio::time::interval(Duration::from_secs(every.max(1)));
 
-    for path in paths {
-        match path {
-            Ok(path_buf) => {
-                charts.push(merger::chart_from_file(OVERRIDE_FOLDER, path_buf).unwrap())
-            }
-            Err(err) => println!("error reading paths: {}", err),
+    loop {
+        interval.tick().await;
+        for err in state.reload(repo).await {
+            eprintln!("could not load chart in {name}: {err}");
+        }
+        if every == 0 {
+            break;
         }
     }
+}
+
+fn load_keys(config: &Config) -> Result<(Option<SecretKey>, Option<Keyring>), Box<dyn Error>> {
+    let signer = match &config.provenance.signing_key {
+        Some(path) => {
+            Some(SecretKey::from_file(path).map_err(|e| format!("{}: {e}", path.display()))?)
+        }
+        None => None,
+    };
+    let keyring = match &config.provenance.keyring {
+        Some(path) => {
+            Some(Keyring::from_file(path).map_err(|e| format!("{}: {e}", path.display()))?)
+        }
+        None => None,
+    };
 
-    charts
+    Ok((signer, keyring))
 }
@@ -0,0 +1,157 @@
+use prometheus::{
+    Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::catalog::Catalog;
+use crate::chart::spec::Version;
+
+pub struct Metrics {
+    registry: Registry,
+    pub requests: IntCounterVec,
+    pub request_duration: HistogramVec,
+    pub downloads: IntCounterVec,
+    // The catalog metrics are per repository. Catalog size is split by where the charts come from,
+    // `local` for the chart roots and uploads, `mirrored` for upstreams.
+    pub catalog_size: IntGaugeVec,
+    pub reload_duration: GaugeVec,
+    pub reload_failures: IntCounterVec,
+    pub merge_errors: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Metrics, prometheus::Error> {
+        let registry = Registry::new_custom(Some(String::from("yaml_web_server")), None)?;
+
+        let metrics = Metrics {
+            requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests by route and status"),
+                &["method", "route", "status"],
+            )?,
+            request_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "http_request_duration_seconds",
+                    "HTTP request latency by route",
+                ),
+                &["method", "route"],
+            )?,
+            downloads: IntCounterVec::new(
+                Opts::new("chart_downloads_total", "Chart archive downloads"),
+                &["chart", "version"],
+            )?,
+            catalog_size: IntGaugeVec::new(
+                Opts::new("catalog_charts", "Chart versions in the catalog"),
+                &["repo", "source"],
+            )?,
+            reload_duration: GaugeVec::new(
+                Opts::new(
+                    "catalog_last_reload_duration_seconds",
+                    "How long the last catalog reload took",
+                ),
+                &["repo"],
+            )?,
+            reload_failures: IntCounterVec::new(
+                Opts::new(
+                    "catalog_reload_failures_total",
+                    "Catalog loads that left some charts out",
+                ),
+                &["repo"],
+            )?,
+            merge_errors: IntCounterVec::new(
+                Opts::new(
+                    "chart_merge_errors_total",
+                    "Charts that could not be read, merged or packaged",
+                ),
+                &["repo"],
+            )?,
+            registry,
+        };
+
+        metrics
+            .registry
+            .register(Box::new(metrics.requests.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.request_duration.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.downloads.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.catalog_size.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.reload_duration.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.reload_failures.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.merge_errors.clone()))?;
+
+        Ok(metrics)
+    }
+
+    pub fn set_catalog_size(&self, repo: &str, catalog: &Catalog) {
+        self.catalog_size
+            .with_label_values(&[repo, "local"])
+            .set(catalog.packages.len() as i64);
+        self.catalog_size
+            .with_label_values(&[repo, "mirrored"])
+            .set(catalog.mirrored.len() as i64);
+    }
+
+    pub fn record_download(&self, name: &str, version: &Version) {
+        self.downloads
+            .with_label_values(&[name, &version.to_string()])
+            .inc();
+    }
+
+    pub fn render(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use reqwest::Url;
+
+    use super::Metrics;
+    use crate::catalog::Catalog;
+    use crate::mirror::MirroredChart;
+
+    #[test]
+    fn test_render_text_format() {
+        let metrics = Metrics::new().unwrap();
+        metrics
+            .downloads
+            .with_label_values(&["test-chart-1", "0.1.0-slug"])
+            .inc();
+        let mut catalog = Catalog::new(Vec::new());
+        catalog.mirrored = vec![
+            MirroredChart {
+                entry: Default::default(),
+                upstream: String::from("https://charts.example.com"),
+                source: Url::parse("https://charts.example.com/app-1.0.0.tgz").unwrap(),
+                cache: Default::default(),
+                timeout: 30,
+            };
+            3
+        ];
+        metrics.set_catalog_size("default", &catalog);
+
+        let text = metrics.render().unwrap();
+
+        assert!(text.contains(
+            "yaml_web_server_chart_downloads_total{chart=\"test-chart-1\",version=\"0.1.0-slug\"} 1"
+        ));
+        assert!(
+            text.contains("yaml_web_server_catalog_charts{repo=\"default\",source=\"local\"} 0")
+        );
+        assert!(
+            text.contains("yaml_web_server_catalog_charts{repo=\"default\",source=\"mirrored\"} 3")
+        );
+    }
+}
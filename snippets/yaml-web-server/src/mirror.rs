@@ -0,0 +1,259 @@
+// Mirrors of remote Helm repositories. Their index is fetched on every catalog load and merged
+// into the served one; archives are only fetched the first time somebody asks for them and are
+// kept on disk from then on, so both keep being served while the upstream is down.
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use chrono::DateTime;
+use reqwest::{Client, Url};
+
+use crate::catalog::{archive_name, checked_archive_name, LoadError, Stage};
+use crate::chart::package;
+use crate::chart::spec::RepositoryEntry;
+use crate::config::UpstreamConfig;
+
+const INDEX_FILE: &str = "index.yaml";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MirroredChart {
+    pub entry: RepositoryEntry,
+    pub upstream: String, // The configured URL of the upstream it came from.
+    pub source: Url,      // Where the upstream serves the archive.
+    pub cache: PathBuf,   // Where the archive is kept once fetched.
+    pub timeout: u64,
+}
+
+impl MirroredChart {
+    pub fn file_name(&self) -> String {
+        archive_name(&self.entry.name, &self.entry.version)
+    }
+
+    pub fn modified(&self) -> SystemTime {
+        DateTime::parse_from_rfc3339(&self.entry.created)
+            .map(SystemTime::from)
+            .unwrap_or(SystemTime::UNIX_EPOCH)
+    }
+}
+
+// Fetches the upstream index, falling back to the copy from the last successful fetch. Entries
+// that do not parse are reported and skipped, the rest still get mirrored.
+pub async fn load(
+    client: &Client,
+    upstream: &UpstreamConfig,
+) -> (Vec<MirroredChart>, Vec<LoadError>) {
+    let mut errors = Vec::new();
+    let base = match Url::parse(&format!("{}/", upstream.url.trim_end_matches('/'))) {
+        Ok(base) => base,
+        Err(err) => return (Vec::new(), vec![load_error(upstream, Stage::Read, err)]),
+    };
+    let cached_index = upstream.cache.join(INDEX_FILE);
+
+    let index = match fetch(client, &format!("{base}{INDEX_FILE}"), upstream.timeout).await {
+        Ok(index) => {
+            if let Err(err) =
+                fs::create_dir_all(&upstream.cache).and_then(|_| fs::write(&cached_index, &index))
+            {
+                errors.push(load_error(
+                    upstream,
+                    Stage::Read,
+                    format!("cannot cache index: {err}"),
+                ));
+            }
+            index
+        }
+        Err(err) => match fs::read(&cached_index) {
+            Ok(index) => {
+                errors.push(load_error(
+                    upstream,
+                    Stage::Read,
+                    format!("upstream unavailable, serving the cached index: {err}"),
+                ));
+                index
+            }
+            Err(_) => return (Vec::new(), vec![load_error(upstream, Stage::Read, err)]),
+        },
+    };
+
+    let index: serde_yaml::Value = match serde_yaml::from_slice(&index) {
+        Ok(index) => index,
+        Err(err) => {
+            errors.push(load_error(upstream, Stage::Parse, err));
+            return (Vec::new(), errors);
+        }
+    };
+
+    let mut charts = Vec::new();
+    let entries = index.get("entries").and_then(|e| e.as_mapping());
+    for versions in entries.into_iter().flat_map(|e| e.values()) {
+        for version in versions.as_sequence().into_iter().flatten() {
+            let entry: RepositoryEntry = match serde_yaml::from_value(version.clone()) {
+                Ok(entry) => entry,
+                Err(err) => {
+                    let name = version.get("name").and_then(|n| n.as_str()).unwrap_or("?");
+                    errors.push(load_error(
+                        upstream,
+                        Stage::Validate,
+                        format!("{name}: {err}"),
+                    ));
+                    continue;
+                }
+            };
+            let Some(source) = entry.urls.first().and_then(|url| base.join(url).ok()) else {
+                errors.push(load_error(
+                    upstream,
+                    Stage::Validate,
+                    format!("{} {} has no download URL", entry.name, entry.version),
+                ));
+                continue;
+            };
+
+            // The upstream picks the names, and they become paths in the cache.
+            let cache = match checked_archive_name(&entry.name, &entry.version) {
+                Ok(file_name) => upstream.cache.join(file_name),
+                Err(err) => {
+                    errors.push(load_error(upstream, Stage::Validate, err));
+                    continue;
+                }
+            };
+            charts.push(MirroredChart {
+                entry,
+                upstream: upstream.url.clone(),
+                source,
+                cache,
+                timeout: upstream.timeout,
+            });
+        }
+    }
+
+    (charts, errors)
+}
+
+// The archive from the cache, or from the upstream the first time it is asked for. Fetched
+// archives are checked against the digest the upstream index announced.
+pub async fn archive(client: &Client, chart: &MirroredChart) -> Result<Vec<u8>, Box<dyn Error>> {
+    if let Ok(archive) = fs::read(&chart.cache) {
+        return Ok(archive);
+    }
+
+    let archive = fetch(client, chart.source.as_str(), chart.timeout).await?;
+    if !chart.entry.digest.is_empty() && package::digest(&archive) != chart.entry.digest {
+        return Err(format!("{} does not match the upstream digest", chart.file_name()).into());
+    }
+
+    if let Some(dir) = chart.cache.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&chart.cache, &archive)?;
+    Ok(archive)
+}
+
+async fn fetch(client: &Client, url: &str, timeout: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+    let response = client
+        .get(url)
+        .timeout(Duration::from_secs(timeout))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(response.bytes().await?.to_vec())
+}
+
+fn load_error(upstream: &UpstreamConfig, stage: Stage, error: impl ToString) -> LoadError {
+    LoadError::at(upstream.url.clone(), stage, error)
+}
+
+#[cfg(test)]
+mod test {
+    use axum::routing::get;
+    use axum::Router;
+    use reqwest::Client;
+
+    use super::{archive, load};
+    use crate::catalog::Stage;
+    use crate::chart::package;
+    use crate::config::UpstreamConfig;
+
+    #[tokio::test]
+    async fn test_mirror_serves_cache_when_upstream_is_down() {
+        let archive_bytes = b"pretend this is a chart".to_vec();
+        let index = format!(
+            r#"
+apiVersion: v1
+entries:
+  demo:
+    - apiVersion: v2
+      name: demo
+      version: 1.0.0
+      created: "2023-03-06T16:54:27Z"
+      description: Demo chart
+      type: application
+      digest: {}
+      urls: [charts/demo-1.0.0.tgz]
+    - apiVersion: v2
+      name: demo
+      version: not-a-version
+      created: "2023-03-06T16:54:27Z"
+      description: Broken entry
+      type: application
+  escaped:
+    - apiVersion: v2
+      name: ../../escaped
+      version: 1.0.0
+      urls: [charts/escaped-1.0.0.tgz]
+generated: "2023-12-20T14:26:26Z"
+"#,
+            package::digest(&archive_bytes)
+        );
+
+        // A local stand-in for the upstream repository.
+        let router = Router::new()
+            .route("/index.yaml", get(move || async move { index }))
+            .route(
+                "/charts/demo-1.0.0.tgz",
+                get(move || async move { archive_bytes }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let upstream_server = tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let tmp = tempfile::tempdir().unwrap();
+        let cache = tmp.path().to_path_buf();
+        let upstream = UpstreamConfig {
+            url: format!("http://{addr}/"),
+            cache: cache.clone(),
+            timeout: 5,
+        };
+        let client = Client::new();
+
+        let (charts, errors) = load(&client, &upstream).await;
+        assert_eq!(charts.len(), 1);
+        assert_eq!(charts[0].file_name(), "demo-1.0.0.tgz");
+        assert_eq!(
+            charts[0].source.as_str(),
+            format!("http://{addr}/charts/demo-1.0.0.tgz")
+        );
+        let stages: Vec<Stage> = errors.iter().map(|e| e.stage).collect();
+        assert_eq!(stages, [Stage::Validate, Stage::Validate]);
+        assert!(
+            errors[1].error.contains("chart name"),
+            "{}",
+            errors[1].error
+        );
+
+        let fetched = archive(&client, &charts[0]).await.unwrap();
+        assert_eq!(fetched, b"pretend this is a chart");
+
+        upstream_server.abort();
+        let _ = upstream_server.await;
+
+        // A fresh client, so no pooled connection outlives the stand-in.
+        let client = Client::new();
+        let (charts, errors) = load(&client, &upstream).await;
+        assert_eq!(charts.len(), 1);
+        assert!(errors[0].error.contains("serving the cached index"));
+        let cached = archive(&client, &charts[0]).await.unwrap();
+        assert_eq!(cached, b"pretend this is a chart");
+    }
+}
@@ -0,0 +1,357 @@
+// OpenPGP for Helm provenance files, on top of rpgp: clearsigning with the configured key the way
+// `helm package --sign` does, and checking signatures against a keyring like `helm verify`.
+use std::error::Error;
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use openpgp::composed::{
+    CleartextSignedMessage, Deserializable, SignedPublicKey, SignedPublicSubKey, SignedSecretKey,
+};
+use openpgp::crypto::hash::HashAlgorithm;
+use openpgp::packet::{self, Signature, SignatureConfig, SignatureType, Subpacket, SubpacketData};
+use openpgp::types::{KeyDetails, Password, Tag, Timestamp};
+
+#[derive(Debug, Clone)]
+pub struct SecretKey {
+    key: SignedSecretKey,
+    subkey: Option<usize>, // Signs instead of the primary key, which may only certify.
+}
+
+// Only the keys that may sign right now: bound to their primary key by a valid self-signature,
+// flagged for signing, and neither revoked nor expired.
+#[derive(Debug, Default, Clone)]
+pub struct Keyring {
+    keys: Vec<SigningKey>,
+}
+
+#[derive(Debug, Clone)]
+enum SigningKey {
+    Primary(packet::PublicKey),
+    Subkey(packet::PublicSubkey),
+}
+
+impl SecretKey {
+    pub fn from_file(path: &Path) -> Result<SecretKey, Box<dyn Error>> {
+        SecretKey::from_bytes(&fs::read(path)?)
+    }
+
+    // The first key that may sign; like gpg, a primary key that may sign is used over its subkeys.
+    pub fn from_bytes(data: &[u8]) -> Result<SecretKey, Box<dyn Error>> {
+        let (keys, _) = SignedSecretKey::from_reader_many(Cursor::new(data))?;
+        let now = Timestamp::now();
+        for key in keys {
+            let key = key?;
+            let public = key.to_public_key();
+            let subkey = if primary_may_sign(&public, now) {
+                None
+            } else {
+                match public
+                    .public_subkeys
+                    .iter()
+                    .position(|subkey| subkey_may_sign(&public, subkey, now))
+                {
+                    Some(index) => Some(index),
+                    None => continue,
+                }
+            };
+
+            let encrypted = match subkey {
+                Some(index) => key.secret_subkeys[index].key.secret_params().is_encrypted(),
+                None => key.primary_key.secret_params().is_encrypted(),
+            };
+            if encrypted {
+                return Err("passphrase-protected signing keys are not supported".into());
+            }
+            return Ok(SecretKey { key, subkey });
+        }
+
+        Err("no secret key that may sign found".into())
+    }
+
+    // Signs at `created` rather than now, so that signing the same text twice gives the same
+    // provenance.
+    pub fn clearsign(&self, text: &str, created: SystemTime) -> Result<String, Box<dyn Error>> {
+        let created = created.duration_since(UNIX_EPOCH)?.as_secs();
+        let created = Timestamp::from_secs(u32::try_from(created)?);
+        let message = match self.subkey {
+            Some(index) => clearsign(text, &self.key.secret_subkeys[index].key, created)?,
+            None => clearsign(text, &self.key.primary_key, created)?,
+        };
+
+        Ok(message.to_armored_string(Default::default())?)
+    }
+}
+
+impl Keyring {
+    pub fn from_file(path: &Path) -> Result<Keyring, Box<dyn Error>> {
+        Keyring::from_bytes(&fs::read(path)?)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Keyring, Box<dyn Error>> {
+        let (keys, _) = SignedPublicKey::from_reader_many(Cursor::new(data))?;
+        let now = Timestamp::now();
+        let mut signing_keys = Vec::new();
+        for key in keys {
+            let key = key?;
+            if primary_may_sign(&key, now) {
+                signing_keys.push(SigningKey::Primary(key.primary_key.clone()));
+            }
+            for subkey in &key.public_subkeys {
+                if subkey_may_sign(&key, subkey, now) {
+                    signing_keys.push(SigningKey::Subkey(subkey.key.clone()));
+                }
+            }
+        }
+
+        if signing_keys.is_empty() {
+            return Err("keyring does not contain any key that may sign".into());
+        }
+
+        Ok(Keyring { keys: signing_keys })
+    }
+
+    // Returns the signed text once a signature checks out: dash-escaping undone, LF line endings
+    // and a single trailing newline, whatever the signer used.
+    pub fn verify_clearsigned(&self, message: &str) -> Result<String, Box<dyn Error>> {
+        let (message, _) = CleartextSignedMessage::from_string(message)?;
+        let text = message.signed_text();
+
+        let mut known = false;
+        for signature in message.signatures() {
+            if signature.typ() != Some(SignatureType::Text) {
+                continue;
+            }
+            for key in &self.keys {
+                if !signature.issuer_key_id().contains(&&key.legacy_key_id()) {
+                    continue;
+                }
+                known = true;
+                let verified = match key {
+                    SigningKey::Primary(key) => signature.verify(key, text.as_bytes()),
+                    SigningKey::Subkey(key) => signature.verify(key, text.as_bytes()),
+                };
+                if verified.is_ok() {
+                    let text = text.replace("\r\n", "\n");
+                    return Ok(format!("{}\n", text.trim_end_matches('\n')));
+                }
+            }
+        }
+
+        if !known {
+            let issuers: Vec<String> = message
+                .signatures()
+                .iter()
+                .flat_map(|s| s.issuer_key_id())
+                .map(hex::encode_upper)
+                .collect();
+            return Err(format!("signed by unknown key {}", issuers.join(", ")).into());
+        }
+        Err("signature does not match the signed text".into())
+    }
+}
+
+impl SigningKey {
+    fn legacy_key_id(&self) -> openpgp::types::KeyId {
+        match self {
+            SigningKey::Primary(key) => key.legacy_key_id(),
+            SigningKey::Subkey(key) => key.legacy_key_id(),
+        }
+    }
+}
+
+fn clearsign(
+    text: &str,
+    key: &impl openpgp::types::SigningKey,
+    created: Timestamp,
+) -> Result<CleartextSignedMessage, Box<dyn Error>> {
+    // Helm signs with SHA-512.
+    let mut config =
+        SignatureConfig::v4(SignatureType::Text, key.algorithm(), HashAlgorithm::Sha512);
+    config.hashed_subpackets = vec![
+        Subpacket::regular(SubpacketData::SignatureCreationTime(created))?,
+        Subpacket::regular(SubpacketData::IssuerFingerprint(key.fingerprint()))?,
+    ];
+    config.unhashed_subpackets = vec![Subpacket::regular(SubpacketData::IssuerKeyId(
+        key.legacy_key_id(),
+    ))?];
+
+    Ok(CleartextSignedMessage::new(
+        text,
+        config,
+        key,
+        &Password::empty(),
+    )?)
+}
+
+// The latest valid self-signature on a user ID carries the primary key's flags and expiry. None
+// once the key is revoked or expired.
+fn self_signature(key: &SignedPublicKey, now: Timestamp) -> Option<&Signature> {
+    let primary = &key.primary_key;
+    let revoked = key
+        .details
+        .revocation_signatures
+        .iter()
+        .any(|sig| sig.verify_key(primary).is_ok());
+    let latest = key
+        .details
+        .users
+        .iter()
+        .flat_map(|user| {
+            user.signatures.iter().filter(|sig| {
+                sig.is_certification()
+                    && sig
+                        .verify_certification(primary, Tag::UserId, &user.id)
+                        .is_ok()
+            })
+        })
+        .max_by_key(|sig| sig.created())?;
+
+    (!revoked && !expired(primary, latest, now)).then_some(latest)
+}
+
+fn primary_may_sign(key: &SignedPublicKey, now: Timestamp) -> bool {
+    self_signature(key, now).is_some_and(|sig| sig.key_flags().sign())
+}
+
+// A subkey needs a valid primary key, a binding signature from it and, since it may sign, the
+// subkey's signature back on the primary key.
+fn subkey_may_sign(key: &SignedPublicKey, subkey: &SignedPublicSubKey, now: Timestamp) -> bool {
+    let primary = &key.primary_key;
+    if self_signature(key, now).is_none() {
+        return false;
+    }
+    let bound = |sig: &&Signature| sig.verify_subkey_binding(primary, &subkey.key).is_ok();
+    let revoked = subkey
+        .signatures
+        .iter()
+        .filter(|sig| sig.typ() == Some(SignatureType::SubkeyRevocation))
+        .any(|sig| bound(&sig));
+    let latest = subkey
+        .signatures
+        .iter()
+        .filter(|sig| sig.typ() == Some(SignatureType::SubkeyBinding))
+        .filter(bound)
+        .max_by_key(|sig| sig.created());
+
+    match latest {
+        Some(sig) => {
+            let back_signed = sig.embedded_signature().is_some_and(|back| {
+                back.verify_primary_key_binding(&subkey.key, primary)
+                    .is_ok()
+            });
+            !revoked && sig.key_flags().sign() && back_signed && !expired(&subkey.key, sig, now)
+        }
+        None => false,
+    }
+}
+
+fn expired(key: &impl KeyDetails, sig: &Signature, now: Timestamp) -> bool {
+    match sig.key_expiration_time() {
+        Some(valid) if valid.as_secs() > 0 => {
+            u64::from(key.created_at().as_secs()) + u64::from(valid.as_secs())
+                <= u64::from(now.as_secs())
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{Keyring, SecretKey};
+
+    const MESSAGE: &str = include_str!("../testdata/pgp/message.txt");
+
+    fn keyring(keys: &[&str]) -> Keyring {
+        Keyring::from_bytes(keys.concat().as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_verifies_gpg_signatures() {
+        let signer = keyring(&[include_str!("../testdata/pgp/signer.pub.asc")]);
+        let prov = include_str!("../testdata/pgp/signer.prov");
+        assert_eq!(signer.verify_clearsigned(prov).unwrap(), MESSAGE);
+
+        // Signed by a subkey, the primary key only certifies.
+        let subkey = keyring(&[include_str!("../testdata/pgp/subkey.pub.asc")]);
+        let prov = include_str!("../testdata/pgp/subkey.prov");
+        assert_eq!(subkey.verify_clearsigned(prov).unwrap(), MESSAGE);
+        assert!(signer
+            .verify_clearsigned(prov)
+            .unwrap_err()
+            .to_string()
+            .contains("unknown key 296BAAF64AE0CE4D"));
+
+        let tampered = prov.replace("version: 1.0.0", "version: 6.6.6");
+        assert_eq!(
+            subkey
+                .verify_clearsigned(&tampered)
+                .unwrap_err()
+                .to_string(),
+            "signature does not match the signed text"
+        );
+    }
+
+    #[test]
+    fn test_unusable_subkeys_are_left_out() {
+        let signer = include_str!("../testdata/pgp/signer.pub.asc");
+        for (key, prov) in [
+            (
+                include_str!("../testdata/pgp/revoked.pub.asc"),
+                include_str!("../testdata/pgp/revoked.prov"),
+            ),
+            (
+                include_str!("../testdata/pgp/expired.pub.asc"),
+                include_str!("../testdata/pgp/expired.prov"),
+            ),
+            // The subkey.prov signer without its binding signature.
+            (
+                include_str!("../testdata/pgp/unbound.pub.asc"),
+                include_str!("../testdata/pgp/subkey.prov"),
+            ),
+        ] {
+            assert!(Keyring::from_bytes(key.as_bytes()).is_err());
+            let keyring = keyring(&[signer, key]);
+            assert!(keyring
+                .verify_clearsigned(prov)
+                .unwrap_err()
+                .to_string()
+                .contains("unknown key"));
+        }
+    }
+
+    #[test]
+    fn test_clearsign_matches_gpg() {
+        let key = SecretKey::from_bytes(include_bytes!("../testdata/pgp/signer.sec.asc")).unwrap();
+        let created = UNIX_EPOCH + Duration::from_secs(1_792_356_000);
+
+        // Checked with `gpg --verify`.
+        let signed = key.clearsign(MESSAGE, created).unwrap();
+        assert_eq!(signed, include_str!("../testdata/pgp/clearsigned.prov"));
+        assert_eq!(key.clearsign(MESSAGE, created).unwrap(), signed);
+
+        let keyring = keyring(&[include_str!("../testdata/pgp/signer.pub.asc")]);
+        assert_eq!(keyring.verify_clearsigned(&signed).unwrap(), MESSAGE);
+
+        // Dash-escaped lines come back as they were.
+        let text = "name: app\n--- not a separator\n";
+        let signed = key.clearsign(text, created).unwrap();
+        assert!(signed.contains("\n- --- not a separator\n"));
+        assert_eq!(keyring.verify_clearsigned(&signed).unwrap(), text);
+    }
+
+    #[test]
+    fn test_signing_subkey_is_used() {
+        let key = SecretKey::from_bytes(include_bytes!("../testdata/pgp/subkey.sec.asc")).unwrap();
+        let signed = key.clearsign(MESSAGE, UNIX_EPOCH + Duration::from_secs(1_792_356_000));
+        let keyring = keyring(&[include_str!("../testdata/pgp/subkey.pub.asc")]);
+
+        assert_eq!(
+            keyring.verify_clearsigned(&signed.unwrap()).unwrap(),
+            MESSAGE
+        );
+    }
+}
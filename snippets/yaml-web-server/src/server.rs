@@ -0,0 +1,1252 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::extract::{MatchedPath, Multipart, Path, Request, State};
+use axum::http::{header, HeaderMap, Method, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post, put};
+use axum::{Extension, Json, Router};
+use caching::Validators;
+use health::LoadStatus;
+use repo::Repo;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use tower_http::compression::predicate::{DefaultPredicate, NotForContentType, Predicate};
+use tower_http::compression::CompressionLayer;
+
+use crate::auth::{Auth, Permission, Principal};
+use crate::catalog::{self, ChartPackage, LoadError, Resolved, Severity};
+use crate::chart::changelog::Changelog;
+use crate::chart::graph::Graph;
+use crate::chart::show::Part;
+use crate::chart::spec::{Bump, Chart, Version};
+use crate::chart::{bump, merger, package, values};
+use crate::config::{Config, DEFAULT_REPO};
+use crate::metrics::Metrics;
+use crate::mirror;
+use crate::pgp::{Keyring, SecretKey};
+
+pub mod caching;
+pub mod health;
+pub mod oci;
+pub mod repo;
+pub mod tls;
+pub mod ui;
+
+pub struct AppState {
+    pub config: Config,
+    pub repos: BTreeMap<String, Repo>,
+    pub signer: Option<SecretKey>,
+    pub keyring: Option<Keyring>,
+    pub auth: Auth,
+    pub metrics: Metrics,
+    pub http: reqwest::Client, // Talks to upstream repositories.
+}
+
+impl AppState {
+    pub fn new(
+        config: Config,
+        signer: Option<SecretKey>,
+        keyring: Option<Keyring>,
+        auth: Auth,
+        metrics: Metrics,
+    ) -> AppState {
+        let base_url = &config.server.base_url;
+        let mut repos = BTreeMap::from([(
+            DEFAULT_REPO.to_string(),
+            Repo::new(DEFAULT_REPO, config.charts.clone(), base_url.clone()),
+        )]);
+        for (name, charts) in &config.repos {
+            let repo = Repo::new(name, charts.clone(), format!("{base_url}/repos/{name}"));
+            repos.insert(name.clone(), repo);
+        }
+
+        AppState {
+            config,
+            repos,
+            signer,
+            keyring,
+            auth,
+            metrics,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    pub fn default_repo(&self) -> &Repo {
+        &self.repos[DEFAULT_REPO]
+    }
+
+    // Rebuilds the repository's catalog from disk. Charts that fail to load are left out and
+    // reported; the rest are swapped in.
+    pub async fn reload(&self, repo: &Repo) -> Vec<LoadError> {
+        let start = Instant::now();
+        let mut report = catalog::load(&repo.charts, self.signer.as_ref(), &self.http).await;
+        self.metrics
+            .reload_duration
+            .with_label_values(&[&repo.name])
+            .set(start.elapsed().as_secs_f64());
+
+        let mut catalog = repo.catalog.write().unwrap_or_else(|e| e.into_inner());
+        catalog.replace(report.packages, report.mirrored);
+        let conflicts = catalog.conflicts(&repo.charts.sources());
+        report
+            .errors
+            .extend(conflicts.into_iter().map(LoadError::from));
+        report.errors.extend(catalog.lint());
+        self.metrics.set_catalog_size(&repo.name, &catalog);
+        drop(catalog);
+
+        // Warnings leave the chart served, so they are not failures.
+        let failed = report
+            .errors
+            .iter()
+            .filter(|e| e.severity == Severity::Error)
+            .count();
+        if failed > 0 {
+            self.metrics
+                .merge_errors
+                .with_label_values(&[&repo.name])
+                .inc_by(failed as u64);
+            self.metrics
+                .reload_failures
+                .with_label_values(&[&repo.name])
+                .inc();
+        }
+
+        let mut status = repo.load.write().unwrap_or_else(|e| e.into_inner());
+        *status = LoadStatus {
+            ready: status.ready || failed == 0,
+            errors: report.errors.clone(),
+        };
+
+        report.errors
+    }
+
+    fn cache_control(&self) -> String {
+        format!("public, max-age={}", self.config.cache.max_age)
+    }
+}
+
+// Routes exist both at the root, for the default repository, and under /repos/:repo.
+fn repo<'a>(state: &'a AppState, params: &HashMap<String, String>) -> Result<&'a Repo, String> {
+    let name = params.get("repo").map_or(DEFAULT_REPO, String::as_str);
+    state
+        .repos
+        .get(name)
+        .ok_or_else(|| format!("repository {name} does not exist"))
+}
+
+pub fn router(state: Arc<AppState>) -> Router {
+    let repo_routes = Router::new()
+        .route("/index.yaml", get(index))
+        .route("/artifacthub-repo.yml", get(artifacthub_repo))
+        .route("/charts/:file", get(chart_file))
+        .route("/api/charts", post(upload))
+        .route("/api/charts/:name/:version", delete(delete_chart))
+        .route(
+            "/api/charts/:name/:version/values",
+            get(chart_values).post(chart_values),
+        )
+        .route(
+            "/api/charts/:name/override",
+            put(edit_override).patch(patch_override),
+        )
+        .route("/api/charts/:name/:version/show/:part", get(show_chart))
+        .route("/api/charts/:name/bump", post(bump_chart))
+        .route("/api/charts/:name/changelog", get(changelog))
+        .route("/api/graph", get(graph))
+        .route("/api/errors", get(health::errors))
+        .route("/ui", get(ui::catalog))
+        .route("/ui/charts/:name", get(ui::chart));
+
+    Router::new()
+        .merge(repo_routes.clone())
+        .nest("/repos/:repo", repo_routes)
+        .route("/v2/", get(oci::base))
+        .route("/v2/*path", get(oci::dispatch))
+        .route("/metrics", get(metrics))
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .route_layer(middleware::from_fn_with_state(state.clone(), authorize))
+        .route_layer(middleware::from_fn_with_state(state.clone(), track))
+        .layer(
+            CompressionLayer::new().compress_when(
+                // Chart archives are gzipped already.
+                DefaultPredicate::new()
+                    .and(NotForContentType::const_new("application/gzip"))
+                    .and(NotForContentType::const_new(oci::CHART_MEDIA_TYPE)),
+            ),
+        )
+        .with_state(state)
+}
+
+// Records every routed request under its route pattern, so `/charts/:file` is one series.
+async fn track(State(state): State<Arc<AppState>>, request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_default();
+    let method = request.method().to_string();
+    let start = Instant::now();
+
+    let response = next.run(request).await;
+
+    state
+        .metrics
+        .request_duration
+        .with_label_values(&[&method, &route])
+        .observe(start.elapsed().as_secs_f64());
+    state
+        .metrics
+        .requests
+        .with_label_values(&[&method, &route, response.status().as_str()])
+        .inc();
+
+    response
+}
+
+// Everything not listed here is a read, and reads are anonymous.
+fn required_permission(method: &Method, route: &str) -> Permission {
+    let route = route.strip_prefix("/repos/:repo").unwrap_or(route);
+    match (method, route) {
+        (&Method::POST, "/api/charts")
+        | (&Method::DELETE, "/api/charts/:name/:version")
+        | (&Method::PUT | &Method::PATCH, "/api/charts/:name/override")
+        | (&Method::POST, "/api/charts/:name/bump") => Permission::Write,
+        _ => Permission::Read,
+    }
+}
+
+// Authenticates the caller and checks the route permission. Chart-name scopes are checked by the
+// handlers, once they know which chart is being written.
+async fn authorize(
+    State(state): State<Arc<AppState>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_default();
+    let required = required_permission(request.method(), &route);
+
+    let principal = match state.auth.authenticate(request.headers()) {
+        Ok(principal) => principal,
+        // Bad credentials on a public route are ignored rather than locking the reader out.
+        Err(_) if required == Permission::Read => Principal::anonymous(),
+        Err(err) => return unauthorized(err),
+    };
+
+    if !principal.can(required) {
+        if principal == Principal::anonymous() {
+            return unauthorized(String::from("authentication required"));
+        }
+        return error(
+            StatusCode::FORBIDDEN,
+            format!("{} does not have write access", principal.subject),
+        );
+    }
+
+    request.extensions_mut().insert(principal);
+    next.run(request).await
+}
+
+fn unauthorized(message: String) -> Response {
+    let mut response = error(StatusCode::UNAUTHORIZED, message);
+    response.headers_mut().insert(
+        header::WWW_AUTHENTICATE,
+        header::HeaderValue::from_static("Basic realm=\"charts\", Bearer"),
+    );
+    response
+}
+
+fn forbidden(principal: &Principal, chart: &str) -> Response {
+    error(
+        StatusCode::FORBIDDEN,
+        format!("{} may not publish {chart}", principal.subject),
+    )
+}
+
+async fn metrics(State(state): State<Arc<AppState>>) -> Response {
+    match state.metrics.render() {
+        Ok(text) => ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn index(
+    State(state): State<Arc<AppState>>,
+    Path(params): Path<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Response {
+    let repo = match repo(&state, &params) {
+        Ok(repo) => repo,
+        Err(err) => return error(StatusCode::NOT_FOUND, err),
+    };
+
+    match repo.cached_index() {
+        Ok(cached) => cached.validators.respond(
+            &headers,
+            state.cache_control(),
+            "application/x-yaml",
+            cached.body.clone(),
+        ),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn artifacthub_repo(
+    State(state): State<Arc<AppState>>,
+    Path(params): Path<HashMap<String, String>>,
+) -> Response {
+    let repo = match repo(&state, &params) {
+        Ok(repo) => repo,
+        Err(err) => return error(StatusCode::NOT_FOUND, err),
+    };
+    let Some(metadata) = &repo.charts.artifact_hub else {
+        return error(
+            StatusCode::NOT_FOUND,
+            format!("repository {} has no Artifact Hub metadata", repo.name),
+        );
+    };
+
+    match serde_yaml::to_string(metadata) {
+        Ok(body) => ([(header::CONTENT_TYPE, "application/x-yaml")], body).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+// Serves both `<chart>-<version>.tgz` and the `<chart>-<version>.tgz.prov` next to it.
+async fn chart_file(
+    State(state): State<Arc<AppState>>,
+    Path(params): Path<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Response {
+    let repo = match repo(&state, &params) {
+        Ok(repo) => repo,
+        Err(err) => return error(StatusCode::NOT_FOUND, err),
+    };
+    let file = &params["file"];
+
+    // The catalog lock cannot be held while an upstream is asked for the archive.
+    let chart = {
+        let Ok(catalog) = repo.catalog.read() else {
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        };
+        let sources = repo.charts.sources();
+
+        if let Some(archive) = file.strip_suffix(".prov") {
+            let package = match catalog.resolve(archive, &sources) {
+                Some(Resolved::Package(package)) => Some(package),
+                _ => None,
+            };
+            return match package.and_then(|p| p.provenance.as_ref().map(|prov| (p, prov))) {
+                Some((package, prov)) => {
+                    Validators::new(&hex::encode(Sha256::digest(prov)), package.modified()).respond(
+                        &headers,
+                        state.cache_control(),
+                        "application/pgp-signature",
+                        prov.clone(),
+                    )
+                }
+                None => StatusCode::NOT_FOUND.into_response(),
+            };
+        }
+
+        match catalog.resolve(file, &sources) {
+            Some(Resolved::Package(package)) => {
+                let response = Validators::new(&package.digest, package.modified()).respond(
+                    &headers,
+                    state.cache_control(),
+                    "application/gzip",
+                    package.archive.clone(),
+                );
+                if response.status() == StatusCode::OK {
+                    state
+                        .metrics
+                        .record_download(&package.chart.name, &package.chart.version);
+                }
+                return response;
+            }
+            Some(Resolved::Mirrored(chart)) => chart.clone(),
+            None => return StatusCode::NOT_FOUND.into_response(),
+        }
+    };
+
+    match mirror::archive(&state.http, &chart).await {
+        Ok(archive) => {
+            let response = Validators::new(&package::digest(&archive), chart.modified()).respond(
+                &headers,
+                state.cache_control(),
+                "application/gzip",
+                archive,
+            );
+            if response.status() == StatusCode::OK {
+                state
+                    .metrics
+                    .record_download(&chart.entry.name, &chart.entry.version);
+            }
+            response
+        }
+        Err(err) => error(StatusCode::BAD_GATEWAY, err.to_string()),
+    }
+}
+
+// Accepts the same multipart fields as ChartMuseum: `chart` and an optional `prov`.
+async fn upload(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(params): Path<HashMap<String, String>>,
+    mut multipart: Multipart,
+) -> Response {
+    let repo = match repo(&state, &params) {
+        Ok(repo) => repo,
+        Err(err) => return error(StatusCode::NOT_FOUND, err),
+    };
+    let mut archive = None;
+    let mut prov = None;
+
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(err) => return error(StatusCode::BAD_REQUEST, err.to_string()),
+        };
+
+        match field.name() {
+            Some("chart") => match field.bytes().await {
+                Ok(bytes) => archive = Some(bytes.to_vec()),
+                Err(err) => return error(StatusCode::BAD_REQUEST, err.to_string()),
+            },
+            Some("prov") => match field.text().await {
+                Ok(text) => prov = Some(text),
+                Err(err) => return error(StatusCode::BAD_REQUEST, err.to_string()),
+            },
+            _ => {}
+        }
+    }
+
+    let Some(archive) = archive else {
+        return error(
+            StatusCode::BAD_REQUEST,
+            String::from("missing `chart` field"),
+        );
+    };
+
+    let package = match ChartPackage::from_upload(
+        archive,
+        prov,
+        state.keyring.as_ref(),
+        state.config.provenance.required,
+    ) {
+        Ok(package) => package,
+        Err(err) => return error(StatusCode::BAD_REQUEST, err.to_string()),
+    };
+    if !principal.can_write(&package.chart.name) {
+        return forbidden(&principal, &package.chart.name);
+    }
+
+    let Ok(mut catalog) = repo.catalog.write() else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    if catalog.find(&package.file_name()).is_some() {
+        return error(
+            StatusCode::CONFLICT,
+            format!("{} already exists", package.file_name()),
+        );
+    }
+    if let Err(err) = catalog::store_upload(&repo.charts.uploads, &package) {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, err.to_string());
+    }
+    catalog.insert(package);
+    state.metrics.set_catalog_size(&repo.name, &catalog);
+
+    (StatusCode::CREATED, Json(json!({ "saved": true }))).into_response()
+}
+
+// Only uploaded charts can be deleted; charts from a chart root would come back on the next load.
+async fn delete_chart(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(params): Path<HashMap<String, String>>,
+) -> Response {
+    let repo = match repo(&state, &params) {
+        Ok(repo) => repo,
+        Err(err) => return error(StatusCode::NOT_FOUND, err),
+    };
+    let (name, version) = (&params["name"], &params["version"]);
+    if !principal.can_write(name) {
+        return forbidden(&principal, name);
+    }
+
+    let Ok(mut catalog) = repo.catalog.write() else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let file_name = format!("{name}-{version}.tgz");
+    let Some(package) = catalog.find(&file_name) else {
+        return error(StatusCode::NOT_FOUND, format!("{file_name} does not exist"));
+    };
+    if package.descriptor.is_some() {
+        return error(
+            StatusCode::CONFLICT,
+            format!("{file_name} comes from a chart root and cannot be deleted"),
+        );
+    }
+
+    if let Err(err) = catalog::delete_upload(&repo.charts.uploads, package) {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, err.to_string());
+    }
+    catalog.remove(&file_name);
+    state.metrics.set_catalog_size(&repo.name, &catalog);
+
+    Json(json!({ "deleted": true })).into_response()
+}
+
+// Replaces the chart's file in the last (highest priority) override folder and reloads.
+async fn edit_override(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(params): Path<HashMap<String, String>>,
+    body: String,
+) -> Response {
+    let repo = match repo(&state, &params) {
+        Ok(repo) => repo,
+        Err(err) => return error(StatusCode::NOT_FOUND, err),
+    };
+    let name = &params["name"];
+    if !principal.can_write(name) {
+        return forbidden(&principal, name);
+    }
+    let (override_dir, descriptor) = match override_target(repo, name) {
+        Ok(target) => target,
+        Err((status, err)) => return error(status, err),
+    };
+
+    match serde_yaml::from_str::<serde_yaml::Value>(&body) {
+        Ok(serde_yaml::Value::Mapping(_)) => {}
+        Ok(_) => {
+            return error(
+                StatusCode::BAD_REQUEST,
+                String::from("an override must be a YAML mapping"),
+            )
+        }
+        Err(err) => return error(StatusCode::BAD_REQUEST, err.to_string()),
+    }
+
+    let file = merger::override_file(&override_dir, &descriptor);
+    let written = file
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(&file, &body));
+    if let Err(err) = written {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, err.to_string());
+    }
+
+    let errors = state.reload(repo).await;
+    Json(json!({ "saved": true, "errors": errors })).into_response()
+}
+
+// Sets the keys of a partial document in the chart's override, leaving the rest of the file,
+// comments included, as it was.
+async fn patch_override(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(params): Path<HashMap<String, String>>,
+    body: String,
+) -> Response {
+    let repo = match repo(&state, &params) {
+        Ok(repo) => repo,
+        Err(err) => return error(StatusCode::NOT_FOUND, err),
+    };
+    let name = &params["name"];
+    if !principal.can_write(name) {
+        return forbidden(&principal, name);
+    }
+    let (override_dir, descriptor) = match override_target(repo, name) {
+        Ok(target) => target,
+        Err((status, err)) => return error(status, err),
+    };
+
+    let patch = match serde_yaml::from_str::<serde_yaml::Value>(&body) {
+        Ok(serde_yaml::Value::Mapping(patch)) if patch.is_empty() => {
+            return error(
+                StatusCode::BAD_REQUEST,
+                String::from("a patch must set at least one key"),
+            )
+        }
+        Ok(patch @ serde_yaml::Value::Mapping(_)) => patch,
+        Ok(_) => {
+            return error(
+                StatusCode::BAD_REQUEST,
+                String::from("a patch must be a YAML mapping"),
+            )
+        }
+        Err(err) => return error(StatusCode::BAD_REQUEST, err.to_string()),
+    };
+    if let Err(err) = merger::patch_override(&override_dir, &descriptor, &patch) {
+        return error(StatusCode::UNPROCESSABLE_ENTITY, err.to_string());
+    }
+
+    let errors = state.reload(repo).await;
+    Json(json!({ "saved": true, "errors": errors })).into_response()
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BumpRequest {
+    part: Bump,
+    #[serde(rename = "appVersion")]
+    app_version: Option<String>,
+    // Dependents' constraints are only proposed unless this is set.
+    #[serde(rename = "updateDependents", default)]
+    update_dependents: bool,
+}
+
+// Releases a chart from a chart root: rewrites its Chart.yaml and reports, or updates, the
+// constraints of the charts that depend on it.
+async fn bump_chart(
+    State(state): State<Arc<AppState>>,
+    Extension(principal): Extension<Principal>,
+    Path(params): Path<HashMap<String, String>>,
+    Json(request): Json<BumpRequest>,
+) -> Response {
+    let repo = match repo(&state, &params) {
+        Ok(repo) => repo,
+        Err(err) => return error(StatusCode::NOT_FOUND, err),
+    };
+    let name = &params["name"];
+    if !principal.can_write(name) {
+        return forbidden(&principal, name);
+    }
+
+    let charts: Vec<(Chart, PathBuf)> = match repo.catalog.read() {
+        Ok(catalog) => catalog
+            .packages
+            .iter()
+            .filter_map(|p| Some((p.chart.clone(), p.descriptor.clone()?)))
+            .collect(),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    if request.update_dependents {
+        let dependents = charts
+            .iter()
+            .filter(|(chart, _)| chart.dependencies.iter().any(|d| d.name == *name));
+        if let Some((dependent, _)) = dependents
+            .into_iter()
+            .find(|(c, _)| !principal.can_write(&c.name))
+        {
+            return forbidden(&principal, &dependent.name);
+        }
+    }
+
+    let report = match bump::bump(
+        charts.iter().map(|(chart, path)| (chart, path.as_path())),
+        &repo.charts.overrides,
+        name,
+        request.part,
+        request.app_version.as_deref(),
+        request.update_dependents,
+    ) {
+        Ok(report) => report,
+        Err(err) => return error(StatusCode::UNPROCESSABLE_ENTITY, err.to_string()),
+    };
+
+    let errors = state.reload(repo).await;
+    Json(json!({ "bump": report, "errors": errors })).into_response()
+}
+
+// The changes listed by every served version of a chart, as JSON or, when asked for with
+// `Accept: text/markdown`, as a Markdown document.
+async fn changelog(
+    State(state): State<Arc<AppState>>,
+    Path(params): Path<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Response {
+    let repo = match repo(&state, &params) {
+        Ok(repo) => repo,
+        Err(err) => return error(StatusCode::NOT_FOUND, err),
+    };
+    let name = &params["name"];
+
+    let index = match repo.catalog.read() {
+        Ok(catalog) => catalog.index(&repo.base_url, &repo.charts.sources()),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let Some(entries) = index.entries.get(name) else {
+        return error(
+            StatusCode::NOT_FOUND,
+            format!("chart {name} does not exist"),
+        );
+    };
+    let (changelog, errors) = Changelog::new(name, entries);
+
+    if accepts(&headers, "text/markdown") {
+        return (
+            [(header::CONTENT_TYPE, "text/markdown; charset=utf-8")],
+            changelog.to_string(),
+        )
+            .into_response();
+    }
+    Json(json!({ "changelog": changelog, "errors": errors })).into_response()
+}
+
+// The values a chart is installed with and the subcharts they leave enabled. A body, when there
+// is one, holds the caller's values, as `helm install -f` would take them.
+async fn chart_values(
+    State(state): State<Arc<AppState>>,
+    Path(params): Path<HashMap<String, String>>,
+    body: String,
+) -> Response {
+    let repo = match repo(&state, &params) {
+        Ok(repo) => repo,
+        Err(err) => return error(StatusCode::NOT_FOUND, err),
+    };
+    let name = &params["name"];
+    let version: Version = match params["version"].parse() {
+        Ok(version) => version,
+        Err(err) => return error(StatusCode::BAD_REQUEST, err),
+    };
+    let user = match serde_yaml::from_str::<serde_yaml::Value>(&body) {
+        Ok(user) if user.is_mapping() || user.is_null() => user,
+        Ok(_) => {
+            return error(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                String::from("values must be a map"),
+            )
+        }
+        Err(err) => return error(StatusCode::UNPROCESSABLE_ENTITY, err.to_string()),
+    };
+
+    match repo.tree(&state.http, name, &version).await {
+        Ok(Some(tree)) => Json(values::evaluate(&tree, user)).into_response(),
+        Ok(None) => error(
+            StatusCode::NOT_FOUND,
+            format!("chart {name} {version} does not exist"),
+        ),
+        Err(err) => error(err.status(), err.to_string()),
+    }
+}
+
+// `helm show` for a served chart: its Chart.yaml, values, README, CRDs or all of them, as YAML
+// (Markdown for the README) or, with `Accept: application/json`, as JSON.
+async fn show_chart(
+    State(state): State<Arc<AppState>>,
+    Path(params): Path<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Response {
+    let repo = match repo(&state, &params) {
+        Ok(repo) => repo,
+        Err(err) => return error(StatusCode::NOT_FOUND, err),
+    };
+    let name = &params["name"];
+    let version: Version = match params["version"].parse() {
+        Ok(version) => version,
+        Err(err) => return error(StatusCode::BAD_REQUEST, err),
+    };
+    let part: Part = match params["part"].parse() {
+        Ok(part) => part,
+        Err(err) => return error(StatusCode::NOT_FOUND, err),
+    };
+
+    let shown = match repo.show(&state.http, name, Some(&version)).await {
+        Ok(Some(shown)) => shown,
+        Ok(None) => {
+            return error(
+                StatusCode::NOT_FOUND,
+                format!("chart {name} {version} does not exist"),
+            )
+        }
+        Err(err) => return error(err.status(), err.to_string()),
+    };
+
+    if accepts(&headers, "application/json") {
+        return match shown.json(part) {
+            Ok(json) => Json(json).into_response(),
+            Err(err) => error(StatusCode::UNPROCESSABLE_ENTITY, err.to_string()),
+        };
+    }
+    let content_type = match part {
+        Part::Readme => "text/markdown; charset=utf-8",
+        _ => "application/x-yaml",
+    };
+    match shown.yaml(part) {
+        Ok(text) => ([(header::CONTENT_TYPE, content_type)], text).into_response(),
+        // The part could not be read out of the chart, a broken CRD for one.
+        Err(err) => error(StatusCode::UNPROCESSABLE_ENTITY, err.to_string()),
+    }
+}
+
+// The dependency graph of the repository's newest chart versions with what is wrong with it, as
+// JSON, or as Graphviz DOT (`Accept: text/vnd.graphviz`) or Mermaid (`Accept: text/vnd.mermaid`).
+async fn graph(
+    State(state): State<Arc<AppState>>,
+    Path(params): Path<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Response {
+    let repo = match repo(&state, &params) {
+        Ok(repo) => repo,
+        Err(err) => return error(StatusCode::NOT_FOUND, err),
+    };
+    let graph = match repo.catalog.read() {
+        Ok(catalog) => Graph::new(&catalog.index(&repo.base_url, &repo.charts.sources())),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    if accepts(&headers, "text/vnd.graphviz") {
+        return (
+            [(header::CONTENT_TYPE, "text/vnd.graphviz")],
+            graph.to_dot(),
+        )
+            .into_response();
+    }
+    if accepts(&headers, "text/vnd.mermaid") {
+        return (
+            [(header::CONTENT_TYPE, "text/vnd.mermaid")],
+            graph.to_mermaid(),
+        )
+            .into_response();
+    }
+    Json(graph).into_response()
+}
+
+// Whether the client listed `media_type` in its Accept header; JSON is served otherwise.
+fn accepts(headers: &HeaderMap, media_type: &str) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains(media_type))
+}
+
+// The override folder that wins and the descriptor of the chart being overridden.
+fn override_target(repo: &Repo, name: &str) -> Result<(PathBuf, PathBuf), (StatusCode, String)> {
+    let Some(override_dir) = repo.charts.overrides.last() else {
+        return Err((
+            StatusCode::CONFLICT,
+            String::from("no override folder is configured"),
+        ));
+    };
+    let descriptor = match repo.catalog.read() {
+        Ok(catalog) => catalog
+            .packages
+            .iter()
+            .filter(|p| p.chart.name == name)
+            .find_map(|p| p.descriptor.clone()),
+        Err(err) => return Err((StatusCode::INTERNAL_SERVER_ERROR, err.to_string())),
+    };
+    match descriptor {
+        Some(descriptor) => Ok((override_dir.clone(), descriptor)),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            format!("{name} is not a chart from a chart root"),
+        )),
+    }
+}
+
+fn error(status: StatusCode, message: String) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use reqwest::{Client, StatusCode};
+    use sha2::{Digest, Sha256};
+
+    use super::{router, AppState};
+    use crate::auth::Auth;
+    use crate::chart::merger;
+    use crate::config::{AuthConfig, ChartsConfig, Config};
+    use crate::metrics::Metrics;
+    use crate::pgp::SecretKey;
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    // Serves the charts under `dir/charts`, overridden from `dir/local`, on a free port. Writes
+    // are open so the handlers can be reached without credentials.
+    async fn serve(dir: &Path) -> String {
+        let (url, state) = start(dir, None).await;
+        state.reload(state.default_repo()).await;
+        url
+    }
+
+    // Like `serve`, but nothing is loaded until the test reloads.
+    async fn start(dir: &Path, signer: Option<SecretKey>) -> (String, Arc<AppState>) {
+        let config = Config {
+            charts: ChartsConfig {
+                roots: vec![dir.join("charts")],
+                overrides: vec![dir.join("local")],
+                uploads: dir.join("uploads"),
+                ..Default::default()
+            },
+            auth: AuthConfig {
+                allow_anonymous_writes: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let auth = Auth::from_config(&config.auth).unwrap();
+        let metrics = Metrics::new().unwrap();
+        let state = Arc::new(AppState::new(config, signer, None, auth, metrics));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = router(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{addr}"), state)
+    }
+
+    #[tokio::test]
+    async fn test_reload_keeps_signed_index() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        write(
+            &dir.join("charts/app/Chart.yaml"),
+            "apiVersion: v2\nname: app\nversion: 1.0.0\n",
+        );
+        let key = include_bytes!("../testdata/pgp/signer.sec.asc");
+        let (url, state) = start(dir, Some(SecretKey::from_bytes(key).unwrap())).await;
+        state.reload(state.default_repo()).await;
+        let client = Client::new();
+
+        let get = |path: &'static str| {
+            let request = client.get(format!("{url}{path}"));
+            async move {
+                let resp = request.send().await.unwrap();
+                assert_eq!(resp.status(), StatusCode::OK);
+                let headers = resp.headers().clone();
+                (headers, resp.text().await.unwrap())
+            }
+        };
+        let (index, _) = get("/index.yaml").await;
+        let (_, provenance) = get("/charts/app-1.0.0.tgz.prov").await;
+
+        // Signature times have a resolution of a second.
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        for _ in 0..2 {
+            state.reload(state.default_repo()).await;
+            let (reloaded, _) = get("/index.yaml").await;
+            assert_eq!(reloaded["etag"], index["etag"]);
+            assert_eq!(reloaded["last-modified"], index["last-modified"]);
+            assert_eq!(get("/charts/app-1.0.0.tgz.prov").await.1, provenance);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_readyz() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let (url, state) = start(dir, None).await;
+        let client = Client::new();
+        let readyz = || async {
+            let resp = client.get(format!("{url}/readyz")).send().await.unwrap();
+            let status = resp.status();
+            let body: serde_json::Value =
+                serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+            (status, body)
+        };
+
+        let (status, body) = readyz().await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["ready"], false);
+
+        // A pass that leaves a chart out is not enough.
+        write(
+            &dir.join("charts/app/Chart.yaml"),
+            "apiVersion: v2\nname: [\n",
+        );
+        state.reload(state.default_repo()).await;
+        let (status, body) = readyz().await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["errors"][0]["severity"], "error");
+
+        write(
+            &dir.join("charts/app/Chart.yaml"),
+            "apiVersion: v2\nname: app\nversion: 1.0.0\n",
+        );
+        state.reload(state.default_repo()).await;
+        let (status, body) = readyz().await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["ready"], true);
+
+        // Once ready, later failures are reported without taking the server out of rotation.
+        write(
+            &dir.join("charts/app/Chart.yaml"),
+            "apiVersion: v2\nname: [\n",
+        );
+        state.reload(state.default_repo()).await;
+        let (status, body) = readyz().await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["errors"][0]["path"]
+            .as_str()
+            .unwrap()
+            .ends_with("charts/app/Chart.yaml"));
+    }
+
+    #[tokio::test]
+    async fn test_oci_dispatch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        for version in ["1.0.0", "1.1.0+up2.0.0"] {
+            write(
+                &dir.join(format!("charts/app-{version}/Chart.yaml")),
+                &format!("apiVersion: v2\nname: app\nversion: {version}\n"),
+            );
+        }
+        let url = serve(dir).await;
+        let client = Client::new();
+        let get = |path: String| {
+            let request = client.get(format!("{url}/v2/{path}"));
+            async move { request.send().await.unwrap() }
+        };
+
+        let resp = get(String::from("charts/app/tags/list")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let tags: serde_json::Value = serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+        assert_eq!(tags["tags"], serde_json::json!(["1.0.0", "1.1.0_up2.0.0"]));
+
+        let resp = get(String::from("charts/app/manifests/1.1.0_up2.0.0")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let digest = resp.headers()["docker-content-digest"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let manifest = resp.text().await.unwrap();
+        let parsed: serde_json::Value = serde_json::from_str(&manifest).unwrap();
+        assert_eq!(
+            parsed["annotations"]["org.opencontainers.image.version"],
+            "1.1.0+up2.0.0"
+        );
+
+        let resp = get(format!("charts/app/manifests/{digest}")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.text().await.unwrap(), manifest);
+
+        let layer = parsed["layers"][0]["digest"].as_str().unwrap();
+        let resp = get(format!("charts/app/blobs/{layer}")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers()["content-type"],
+            "application/vnd.cncf.helm.chart.content.v1.tar+gzip"
+        );
+        let blob = resp.bytes().await.unwrap();
+        assert_eq!(
+            format!("sha256:{}", hex::encode(Sha256::digest(&blob))),
+            layer
+        );
+
+        for (path, code) in [
+            ("charts/app/manifests/9.9.9", "MANIFEST_UNKNOWN"),
+            ("charts/app/blobs/sha256:00", "BLOB_UNKNOWN"),
+            ("charts/nope/tags/list", "NAME_UNKNOWN"),
+            ("app/tags/list", "NAME_UNKNOWN"),
+            ("other/app/tags/list", "NAME_UNKNOWN"),
+            ("charts/nested/app/tags/list", "NAME_UNKNOWN"),
+        ] {
+            let resp = get(String::from(path)).await;
+            assert_eq!(resp.status(), StatusCode::NOT_FOUND, "{path}");
+            let body: serde_json::Value =
+                serde_json::from_str(&resp.text().await.unwrap()).unwrap();
+            assert_eq!(body["errors"][0]["code"], code, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_patch_override() {
+        let tmp = tempfile::tempdir().unwrap();
+        let descriptor = tmp.path().join("charts/app/Chart.yaml");
+        write(
+            &descriptor,
+            "apiVersion: v2\nname: app\nversion: 1.0.0\ndescription: first\n",
+        );
+        let override_file = merger::override_file(&tmp.path().join("local"), &descriptor);
+        let original = "# Set by the platform team.\ndescription: second\n";
+        write(&override_file, original);
+        let base = serve(tmp.path()).await;
+        let client = Client::new();
+        let url = format!("{base}/api/charts/app/override");
+
+        let response = client.patch(&url).body("{}").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(fs::read_to_string(&override_file).unwrap(), original);
+
+        let response = client
+            .patch(&url)
+            .body("description: third\n")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            fs::read_to_string(&override_file).unwrap(),
+            "# Set by the platform team.\ndescription: third\n"
+        );
+        let index = client
+            .get(format!("{base}/index.yaml"))
+            .send()
+            .await
+            .unwrap();
+        let index = index.text().await.unwrap();
+        assert!(index.contains("description: third"), "{index}");
+    }
+
+    #[tokio::test]
+    async fn test_chart_values() {
+        let tmp = tempfile::tempdir().unwrap();
+        let charts = tmp.path().join("charts");
+        write(
+            &charts.join("app/Chart.yaml"),
+            "apiVersion: v2\nname: app\nversion: 1.0.0\ndependencies:\n  - name: db\n    version: ^1.0.0\n    condition: db.enabled\n",
+        );
+        write(
+            &charts.join("app/values.yaml"),
+            "replicas: 1\ndb:\n  enabled: false\n",
+        );
+        write(
+            &charts.join("db/Chart.yaml"),
+            "apiVersion: v2\nname: db\nversion: 1.2.0\n",
+        );
+        write(&charts.join("db/values.yaml"), "size: 1Gi\n");
+        write(
+            &charts.join("broken/Chart.yaml"),
+            "apiVersion: v2\nname: broken\nversion: 1.0.0\n",
+        );
+        write(&charts.join("broken/values.yaml"), "size: [1Gi\n");
+        let base = serve(tmp.path()).await;
+        let client = Client::new();
+        let url = format!("{base}/api/charts/app/1.0.0/values");
+
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let json: serde_json::Value =
+            serde_json::from_str(&response.text().await.unwrap()).unwrap();
+        assert_eq!(json["values"]["replicas"], 1);
+        assert_eq!(json["subcharts"][0]["enabled"], false);
+
+        let response = client
+            .post(&url)
+            .body("replicas: 3\ndb:\n  enabled: true\n")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let json: serde_json::Value =
+            serde_json::from_str(&response.text().await.unwrap()).unwrap();
+        assert_eq!(json["values"]["replicas"], 3);
+        assert_eq!(json["values"]["db"]["size"], "1Gi");
+        assert_eq!(json["subcharts"][0]["enabled"], true);
+
+        let response = client.post(&url).body("- a list").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let response = client
+            .get(format!("{base}/api/charts/app/2.0.0/values"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        // A broken chart is the chart's fault, not an upstream's.
+        let response = client
+            .get(format!("{base}/api/charts/broken/1.0.0/values"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_show_chart() {
+        let tmp = tempfile::tempdir().unwrap();
+        let charts = tmp.path().join("charts");
+        write(
+            &charts.join("app/Chart.yaml"),
+            "apiVersion: v2\nname: app\nversion: 1.0.0\n",
+        );
+        write(&charts.join("app/values.yaml"), "# Pods.\nreplicas: 1\n");
+        write(
+            &charts.join("app/crds/widgets.yaml"),
+            "kind: CustomResourceDefinition\n",
+        );
+        write(
+            &charts.join("broken/Chart.yaml"),
+            "apiVersion: v2\nname: broken\nversion: 1.0.0\n",
+        );
+        write(&charts.join("broken/crds/widgets.yaml"), "kind: [\n");
+        let base = serve(tmp.path()).await;
+        let client = Client::new();
+        let url = |chart: &str, part: &str| format!("{base}/api/charts/{chart}/1.0.0/show/{part}");
+
+        let response = client.get(url("app", "values")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "application/x-yaml");
+        assert_eq!(response.text().await.unwrap(), "# Pods.\nreplicas: 1\n");
+
+        let response = client
+            .get(url("app", "crds"))
+            .header("accept", "application/json")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        let json: serde_json::Value =
+            serde_json::from_str(&response.text().await.unwrap()).unwrap();
+        assert_eq!(json[0]["file"], "widgets.yaml");
+
+        let response = client.get(url("app", "everything")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = client.get(url("broken", "chart")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = client.get(url("broken", "crds")).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_graph() {
+        let tmp = tempfile::tempdir().unwrap();
+        let charts = tmp.path().join("charts");
+        write(
+            &charts.join("app/Chart.yaml"),
+            "apiVersion: v2\nname: app\nversion: 1.0.0\ndependencies:\n  - name: db\n    version: ^1.0.0\n",
+        );
+        write(
+            &charts.join("db/Chart.yaml"),
+            "apiVersion: v2\nname: db\nversion: 1.2.0\n",
+        );
+        let base = serve(tmp.path()).await;
+        let client = Client::new();
+        let url = format!("{base}/api/graph");
+
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        let json: serde_json::Value =
+            serde_json::from_str(&response.text().await.unwrap()).unwrap();
+        assert_eq!(json["edges"][0]["from"], "app");
+        assert_eq!(json["edges"][0]["resolved"], "1.2.0");
+
+        for media_type in ["text/vnd.graphviz", "text/vnd.mermaid"] {
+            let response = client
+                .get(&url)
+                .header("accept", media_type)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()["content-type"], media_type);
+            let body = response.text().await.unwrap();
+            assert!(body.contains("app") && body.contains("db"), "{body}");
+        }
+    }
+}
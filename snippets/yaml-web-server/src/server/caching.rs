@@ -0,0 +1,126 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+
+// Validators for a representation: an ETag and the time it last changed. The tag is weak because
+// the compression layer may send the same representation gzip or br encoded under it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Validators {
+    pub etag: String,
+    pub last_modified: SystemTime,
+}
+
+impl Validators {
+    pub fn new(etag: &str, last_modified: SystemTime) -> Validators {
+        Validators {
+            etag: format!("W/\"{etag}\""),
+            last_modified,
+        }
+    }
+
+    // If-None-Match wins over If-Modified-Since when both are sent (RFC 9110 13.2.2), and compares
+    // tags weakly.
+    pub fn not_modified(&self, request: &HeaderMap) -> bool {
+        if let Some(if_none_match) = request.get(header::IF_NONE_MATCH) {
+            let Ok(if_none_match) = if_none_match.to_str() else {
+                return false;
+            };
+            return if_none_match
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || weak(tag) == weak(&self.etag));
+        }
+
+        let if_modified_since = request
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| DateTime::parse_from_rfc2822(v).ok());
+        match if_modified_since {
+            Some(since) => truncate(self.last_modified) <= SystemTime::from(since),
+            None => false,
+        }
+    }
+
+    pub fn respond(
+        &self,
+        request: &HeaderMap,
+        cache_control: String,
+        content_type: &str,
+        body: impl IntoResponse,
+    ) -> Response {
+        let headers = [
+            (header::ETAG, self.etag.clone()),
+            (header::LAST_MODIFIED, http_date(self.last_modified)),
+            (header::CACHE_CONTROL, cache_control),
+        ];
+
+        if self.not_modified(request) {
+            return (StatusCode::NOT_MODIFIED, headers).into_response();
+        }
+
+        (
+            headers,
+            [(header::CONTENT_TYPE, content_type.to_string())],
+            body,
+        )
+            .into_response()
+    }
+}
+
+pub fn http_date(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+fn weak(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+// HTTP dates only carry whole seconds.
+fn truncate(time: SystemTime) -> SystemTime {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use axum::http::{header, HeaderMap, HeaderValue};
+
+    use super::{http_date, Validators};
+
+    #[test]
+    fn test_conditional_requests() {
+        let modified = UNIX_EPOCH + Duration::from_millis(1_700_000_000_500);
+        let validators = Validators::new("abc", modified);
+        assert_eq!(validators.etag, "W/\"abc\"");
+
+        let mut request = HeaderMap::new();
+        assert!(!validators.not_modified(&request));
+
+        request.insert(
+            header::IF_MODIFIED_SINCE,
+            HeaderValue::from_str(&http_date(modified)).unwrap(),
+        );
+        assert!(validators.not_modified(&request));
+
+        // A mismatching ETag means modified, whatever If-Modified-Since says.
+        request.insert(header::IF_NONE_MATCH, HeaderValue::from_static("\"def\""));
+        assert!(!validators.not_modified(&request));
+
+        request.insert(
+            header::IF_NONE_MATCH,
+            HeaderValue::from_static("\"def\", W/\"abc\""),
+        );
+        assert!(validators.not_modified(&request));
+        request.insert(header::IF_NONE_MATCH, HeaderValue::from_static("\"abc\""));
+        assert!(validators.not_modified(&request));
+    }
+}
@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use serde_json::json;
+
+use super::AppState;
+use crate::catalog::LoadError;
+
+// `ready` flips once a catalog load pass gets through without errors and stays up afterwards.
+// `errors` are what the last pass found: charts it left out, conflicts and lint warnings, told
+// apart by their severity.
+#[derive(Debug, Default, Serialize, Clone, PartialEq)]
+pub struct LoadStatus {
+    pub ready: bool,
+    pub errors: Vec<LoadError>,
+}
+
+pub async fn healthz() -> Response {
+    Json(json!({ "status": "ok" })).into_response()
+}
+
+// Ready once every repository has had a load pass without errors.
+pub async fn readyz(State(state): State<Arc<AppState>>) -> Response {
+    let mut ready = true;
+    let mut errors = Vec::new();
+    for repo in state.repos.values() {
+        let Ok(status) = repo.load.read() else {
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        };
+        ready &= status.ready;
+        errors.extend(status.errors.iter().cloned());
+    }
+
+    let code = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (code, Json(LoadStatus { ready, errors })).into_response()
+}
+
+pub async fn errors(
+    State(state): State<Arc<AppState>>,
+    Path(params): Path<HashMap<String, String>>,
+) -> Response {
+    let repo = match super::repo(&state, &params) {
+        Ok(repo) => repo,
+        Err(err) => return super::error(StatusCode::NOT_FOUND, err),
+    };
+    let Ok(status) = repo.load.read() else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    Json(json!({ "errors": status.errors })).into_response()
+}
@@ -0,0 +1,352 @@
+// OCI distribution (pull side) on top of the catalog, so `helm pull oci://<host>/charts/<name>`
+// works next to the classic index.yaml. Every chart version is an artifact tagged with its version.
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+use axum::http::header;
+use axum::http::{HeaderName, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use super::AppState;
+use crate::catalog::{Catalog, ChartPackage};
+
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+const CONFIG_MEDIA_TYPE: &str = "application/vnd.cncf.helm.config.v1+json";
+pub const CHART_MEDIA_TYPE: &str = "application/vnd.cncf.helm.chart.content.v1.tar+gzip";
+const PROVENANCE_MEDIA_TYPE: &str = "application/vnd.cncf.helm.chart.provenance.v1.prov";
+
+const API_VERSION_HEADER: HeaderName = HeaderName::from_static("docker-distribution-api-version");
+const DIGEST_HEADER: HeaderName = HeaderName::from_static("docker-content-digest");
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Manifest {
+    #[serde(rename = "schemaVersion")]
+    pub schema_version: u32,
+    #[serde(rename = "mediaType")]
+    pub media_type: String,
+    pub config: Descriptor,
+    pub layers: Vec<Descriptor>,
+    pub annotations: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Descriptor {
+    #[serde(rename = "mediaType")]
+    pub media_type: String,
+    pub digest: String,
+    pub size: usize,
+}
+
+// Every chart version as an artifact, built once for each catalog generation rather than on every
+// request. Charts that cannot be made into an artifact keep the error for their requests.
+pub struct Artifacts {
+    pub generation: u64,
+    charts: BTreeMap<String, Result<Vec<(String, Artifact)>, String>>, // By name, then tag.
+}
+
+impl Artifacts {
+    pub fn new(catalog: &Catalog) -> Artifacts {
+        let mut charts: BTreeMap<String, Result<Vec<(String, Artifact)>, String>> = BTreeMap::new();
+        for package in &catalog.packages {
+            let entry = charts
+                .entry(package.chart.name.clone())
+                .or_insert_with(|| Ok(Vec::new()));
+            let Ok(artifacts) = entry else {
+                continue;
+            };
+            match Artifact::new(package) {
+                Ok(artifact) => artifacts.push((tag(package), artifact)),
+                Err(err) => *entry = Err(err.to_string()),
+            }
+        }
+
+        Artifacts {
+            generation: catalog.generation,
+            charts,
+        }
+    }
+}
+
+// An artifact is the manifest plus every blob it references, keyed by digest.
+struct Artifact {
+    manifest: Vec<u8>,
+    blobs: BTreeMap<String, (&'static str, Vec<u8>)>,
+}
+
+impl Artifact {
+    fn new(package: &ChartPackage) -> Result<Artifact, Box<dyn std::error::Error>> {
+        let config: serde_json::Value = serde_yaml::from_str(&package.chart_yaml)?;
+        let mut blobs = BTreeMap::new();
+
+        let config = blob(&mut blobs, CONFIG_MEDIA_TYPE, serde_json::to_vec(&config)?);
+        let mut layers = vec![blob(&mut blobs, CHART_MEDIA_TYPE, package.archive.clone())];
+        if let Some(prov) = &package.provenance {
+            layers.push(blob(
+                &mut blobs,
+                PROVENANCE_MEDIA_TYPE,
+                prov.clone().into_bytes(),
+            ));
+        }
+
+        let manifest = Manifest {
+            schema_version: 2,
+            media_type: String::from(MANIFEST_MEDIA_TYPE),
+            config,
+            layers,
+            annotations: BTreeMap::from([
+                (
+                    String::from("org.opencontainers.image.title"),
+                    package.chart.name.clone(),
+                ),
+                (
+                    String::from("org.opencontainers.image.version"),
+                    package.chart.version.to_string(),
+                ),
+                (
+                    String::from("org.opencontainers.image.description"),
+                    package.chart.description.clone(),
+                ),
+                (
+                    String::from("org.opencontainers.image.created"),
+                    package.created.clone(),
+                ),
+            ]),
+        };
+
+        Ok(Artifact {
+            manifest: serde_json::to_vec(&manifest)?,
+            blobs,
+        })
+    }
+
+    fn digest(&self) -> String {
+        sha256(&self.manifest)
+    }
+}
+
+fn blob(
+    blobs: &mut BTreeMap<String, (&'static str, Vec<u8>)>,
+    media_type: &'static str,
+    data: Vec<u8>,
+) -> Descriptor {
+    let descriptor = Descriptor {
+        media_type: String::from(media_type),
+        digest: sha256(&data),
+        size: data.len(),
+    };
+    blobs.insert(descriptor.digest.clone(), (media_type, data));
+    descriptor
+}
+
+fn sha256(data: &[u8]) -> String {
+    format!("sha256:{}", hex::encode(Sha256::digest(data)))
+}
+
+// OCI tags cannot hold `+`, so Helm swaps build metadata separators for `_`.
+fn tag(package: &ChartPackage) -> String {
+    package.chart.version.to_string().replace('+', "_")
+}
+
+pub async fn base() -> Response {
+    ([(API_VERSION_HEADER, "registry/2.0")], Json(json!({}))).into_response()
+}
+
+// Repository names hold a `/` (`charts/foo`), so the path is split by hand on the last
+// `/manifests/`, `/blobs/` or `/tags/list` segment.
+pub async fn dispatch(State(state): State<Arc<AppState>>, Path(path): Path<String>) -> Response {
+    if let Some(name) = path.strip_suffix("/tags/list") {
+        return tags(&state, name);
+    }
+    if let Some((name, reference)) = path.rsplit_once("/manifests/") {
+        return manifest(&state, name, reference);
+    }
+    if let Some((name, digest)) = path.rsplit_once("/blobs/") {
+        return blob_content(&state, name, digest);
+    }
+
+    error(StatusCode::NOT_FOUND, "UNSUPPORTED", "unsupported endpoint")
+}
+
+// Charts live at `charts/<name>`, the path `helm pull oci://<host>/charts/<name>` asks for.
+fn chart_name(name: &str) -> Option<&str> {
+    name.strip_prefix("charts/").filter(|n| !n.contains('/'))
+}
+
+// Errors carry the status, the OCI error code and a message.
+type OciError = (StatusCode, &'static str, String);
+
+// Runs `f` on the versions of the chart at `name`.
+fn with_artifacts<T>(
+    state: &AppState,
+    name: &str,
+    f: impl FnOnce(&[(String, Artifact)]) -> T,
+) -> Result<T, OciError> {
+    let cached = state.default_repo().artifacts().map_err(|err| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "UNKNOWN",
+            err.to_string(),
+        )
+    })?;
+
+    match chart_name(name).and_then(|chart| cached.charts.get(chart)) {
+        Some(Ok(artifacts)) => Ok(f(artifacts)),
+        Some(Err(err)) => Err((StatusCode::INTERNAL_SERVER_ERROR, "UNKNOWN", err.clone())),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            "NAME_UNKNOWN",
+            format!("repository {name} is not known to this registry"),
+        )),
+    }
+}
+
+fn tags(state: &AppState, name: &str) -> Response {
+    let tags = with_artifacts(state, name, |artifacts| {
+        artifacts.iter().map(|(tag, _)| tag.clone()).collect()
+    });
+    let mut tags: Vec<String> = match tags {
+        Ok(tags) => tags,
+        Err((status, code, message)) => return error(status, code, &message),
+    };
+    tags.sort();
+
+    Json(json!({ "name": name, "tags": tags })).into_response()
+}
+
+fn manifest(state: &AppState, name: &str, reference: &str) -> Response {
+    let found = with_artifacts(state, name, |artifacts| {
+        artifacts
+            .iter()
+            .find(|(tag, artifact)| tag == reference || artifact.digest() == reference)
+            .map(|(_, artifact)| (artifact.digest(), artifact.manifest.clone()))
+    });
+
+    match found {
+        Err((status, code, message)) => error(status, code, &message),
+        Ok(Some((digest, manifest))) => (
+            [
+                (header::CONTENT_TYPE, String::from(MANIFEST_MEDIA_TYPE)),
+                (DIGEST_HEADER, digest),
+            ],
+            manifest,
+        )
+            .into_response(),
+        Ok(None) => error(
+            StatusCode::NOT_FOUND,
+            "MANIFEST_UNKNOWN",
+            &format!("manifest {reference} is not known to this registry"),
+        ),
+    }
+}
+
+fn blob_content(state: &AppState, name: &str, digest: &str) -> Response {
+    let found = with_artifacts(state, name, |artifacts| {
+        artifacts
+            .iter()
+            .find_map(|(_, artifact)| artifact.blobs.get(digest).cloned())
+    });
+
+    match found {
+        Err((status, code, message)) => error(status, code, &message),
+        Ok(Some((media_type, data))) => {
+            if media_type == CHART_MEDIA_TYPE {
+                record_download(state, digest);
+            }
+            (
+                [
+                    (header::CONTENT_TYPE, String::from(media_type)),
+                    (DIGEST_HEADER, String::from(digest)),
+                ],
+                data,
+            )
+                .into_response()
+        }
+        Ok(None) => error(
+            StatusCode::NOT_FOUND,
+            "BLOB_UNKNOWN",
+            &format!("blob {digest} is not known to this registry"),
+        ),
+    }
+}
+
+fn record_download(state: &AppState, digest: &str) {
+    let Ok(catalog) = state.default_repo().catalog.read() else {
+        return;
+    };
+    if let Some(package) = catalog
+        .packages
+        .iter()
+        .find(|p| digest == format!("sha256:{}", p.digest))
+    {
+        state
+            .metrics
+            .record_download(&package.chart.name, &package.chart.version);
+    }
+}
+
+fn error(status: StatusCode, code: &str, message: &str) -> Response {
+    (
+        status,
+        Json(json!({ "errors": [{ "code": code, "message": message, "detail": {} }] })),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::Artifact;
+    use crate::catalog::ChartPackage;
+    use crate::chart::spec::{Chart, Version};
+    use crate::config::ChartsConfig;
+    use crate::server::repo::Repo;
+
+    #[test]
+    fn test_artifact_references_chart_layer_and_config() {
+        let package = ChartPackage {
+            chart: Chart {
+                name: String::from("test-chart-1"),
+                version: Version {
+                    major: 0,
+                    minor: 1,
+                    bugfix: 0,
+                    slug: String::from("slug"),
+                    build: String::new(),
+                },
+                ..Default::default()
+            },
+            chart_yaml: String::from("apiVersion: v2\nname: test-chart-1\nversion: 0.1.0-slug\n"),
+            archive: b"not really a tarball".to_vec(),
+            digest: String::new(),
+            provenance: None,
+            created: String::new(),
+            descriptor: None,
+        };
+
+        let artifact = Artifact::new(&package).unwrap();
+        let manifest: serde_json::Value = serde_json::from_slice(&artifact.manifest).unwrap();
+
+        assert_eq!(manifest["config"]["mediaType"], super::CONFIG_MEDIA_TYPE);
+        assert_eq!(manifest["layers"][0]["mediaType"], super::CHART_MEDIA_TYPE);
+        assert_eq!(manifest["layers"][0]["size"], 20);
+
+        let config_digest = manifest["config"]["digest"].as_str().unwrap();
+        let (_, config) = &artifact.blobs[config_digest];
+        let config: serde_json::Value = serde_json::from_slice(config).unwrap();
+        assert_eq!(config["version"], "0.1.0-slug");
+
+        // Built once per catalog generation.
+        let repo = Repo::new("default", ChartsConfig::default(), String::new());
+        repo.catalog.write().unwrap().insert(package.clone());
+        let artifacts = repo.artifacts().unwrap();
+        assert!(Arc::ptr_eq(&artifacts, &repo.artifacts().unwrap()));
+        repo.catalog.write().unwrap().insert(package);
+        assert!(!Arc::ptr_eq(&artifacts, &repo.artifacts().unwrap()));
+    }
+}
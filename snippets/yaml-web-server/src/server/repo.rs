@@ -0,0 +1,309 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
+
+use axum::body::Bytes;
+use axum::http::StatusCode;
+use reqwest::Client;
+use serde_yaml::Value;
+use sha2::{Digest, Sha256};
+
+use super::caching::Validators;
+use super::health::LoadStatus;
+use super::oci::Artifacts;
+use crate::catalog::{archive_name, Catalog, Resolved};
+use crate::chart::constraint::Constraint;
+use crate::chart::package::{self, NamedArchive};
+use crate::chart::show::Shown;
+use crate::chart::spec::{Chart, Dependency, Repository, Version};
+use crate::chart::values::Tree;
+use crate::config::ChartsConfig;
+use crate::mirror;
+
+// One chart repository: its own roots and overrides, catalog, load state and cached index.
+pub struct Repo {
+    pub name: String,
+    pub charts: ChartsConfig,
+    pub base_url: String, // Where its index points chart downloads at.
+    pub catalog: RwLock<Catalog>,
+    pub load: RwLock<LoadStatus>,
+    index_cache: Mutex<Option<Arc<CachedIndex>>>,
+    artifact_cache: Mutex<Option<Arc<Artifacts>>>,
+}
+
+// Why a served chart could not be read: its upstream did not hand the archive over, the chart
+// itself is broken, or the server is.
+#[derive(Debug)]
+pub enum ChartError {
+    Upstream(String),
+    Content(String),
+    Internal(String),
+}
+
+impl ChartError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ChartError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            ChartError::Content(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ChartError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for ChartError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChartError::Upstream(msg) | ChartError::Content(msg) | ChartError::Internal(msg) => {
+                f.write_str(msg)
+            }
+        }
+    }
+}
+
+impl Error for ChartError {}
+
+// The serialized index.yaml, kept until the catalog generation it was built from moves on.
+pub struct CachedIndex {
+    generation: u64,
+    pub body: Bytes,
+    pub validators: Validators,
+}
+
+impl Repo {
+    pub fn new(name: &str, charts: ChartsConfig, base_url: String) -> Repo {
+        Repo {
+            name: name.to_string(),
+            charts,
+            base_url,
+            catalog: RwLock::new(Catalog::new(Vec::new())),
+            load: RwLock::new(LoadStatus::default()),
+            index_cache: Mutex::new(None),
+            artifact_cache: Mutex::new(None),
+        }
+    }
+
+    pub fn cached_index(&self) -> Result<Arc<CachedIndex>, Box<dyn Error>> {
+        let catalog = self.catalog.read().map_err(|e| e.to_string())?;
+        let mut cache = self.index_cache.lock().map_err(|e| e.to_string())?;
+
+        if let Some(cached) = cache.as_ref() {
+            if cached.generation == catalog.generation {
+                return Ok(cached.clone());
+            }
+        }
+
+        let body = serde_yaml::to_string(&catalog.index(&self.base_url, &self.charts.sources()))?;
+        let cached = Arc::new(CachedIndex {
+            generation: catalog.generation,
+            validators: Validators::new(&hex::encode(Sha256::digest(&body)), catalog.modified),
+            body: Bytes::from(body),
+        });
+        *cache = Some(cached.clone());
+
+        Ok(cached)
+    }
+
+    // The charts as OCI artifacts, rebuilt when the catalog generation moves on.
+    pub fn artifacts(&self) -> Result<Arc<Artifacts>, Box<dyn Error>> {
+        let catalog = self.catalog.read().map_err(|e| e.to_string())?;
+        let mut cache = self.artifact_cache.lock().map_err(|e| e.to_string())?;
+
+        if let Some(cached) = cache.as_ref() {
+            if cached.generation == catalog.generation {
+                return Ok(cached.clone());
+            }
+        }
+
+        let cached = Arc::new(Artifacts::new(&catalog));
+        *cache = Some(cached.clone());
+
+        Ok(cached)
+    }
+
+    // The archive served under `file_name`, fetched from its upstream when it is mirrored.
+    pub async fn archive(
+        &self,
+        http: &Client,
+        file_name: &str,
+    ) -> Result<Option<Vec<u8>>, ChartError> {
+        let mirrored = {
+            let catalog = self
+                .catalog
+                .read()
+                .map_err(|e| ChartError::Internal(e.to_string()))?;
+            match catalog.resolve(file_name, &self.charts.sources()) {
+                Some(Resolved::Package(package)) => return Ok(Some(package.archive.clone())),
+                Some(Resolved::Mirrored(chart)) => chart.clone(),
+                None => return Ok(None),
+            }
+        };
+        match mirror::archive(http, &mirrored).await {
+            Ok(archive) => Ok(Some(archive)),
+            Err(err) => Err(ChartError::Upstream(format!("{file_name}: {err}"))),
+        }
+    }
+
+    // A served chart with its subcharts under it. A subchart vendored in the chart's `charts/`
+    // folder is used when its version fits; otherwise the newest served chart the dependency
+    // accepts is. Dependencies are looked up by name in this repository whatever their
+    // `repository` says, and one on a chart further up is left unresolved instead of being
+    // followed round.
+    pub async fn tree(
+        &self,
+        http: &Client,
+        name: &str,
+        version: &Version,
+    ) -> Result<Option<Tree>, ChartError> {
+        let index = self.index()?;
+        let Some(archive) = self.archive(http, &archive_name(name, version)).await? else {
+            return Ok(None);
+        };
+        self.subtree(http, &index, archive, &mut Vec::new())
+            .await
+            .map(Some)
+    }
+
+    // A served chart as `helm show` reads it; the newest version when none is given.
+    pub async fn show(
+        &self,
+        http: &Client,
+        name: &str,
+        version: Option<&Version>,
+    ) -> Result<Option<Shown>, ChartError> {
+        let index = self.index()?;
+        let newest = index
+            .entries
+            .get(name)
+            .and_then(|entries| entries.iter().map(|e| &e.version).max());
+        let Some(version) = version.or(newest) else {
+            return Ok(None);
+        };
+        let Some(archive) = self.archive(http, &archive_name(name, version)).await? else {
+            return Ok(None);
+        };
+        Shown::new(&archive)
+            .map(Some)
+            .map_err(|e| ChartError::Content(format!("{name} {version}: {e}")))
+    }
+
+    // The archives `dependency build` vendors for `chart`: the newest served chart each
+    // dependency accepts. Aliases of the same chart share one archive.
+    pub async fn dependency_archives(
+        &self,
+        http: &Client,
+        chart: &Chart,
+    ) -> Result<Vec<NamedArchive>, ChartError> {
+        let index = self.index()?;
+        let mut archives: Vec<NamedArchive> = Vec::new();
+        for dependency in &chart.dependencies {
+            let Some(version) = newest(&index, dependency) else {
+                return Err(ChartError::Content(format!(
+                    "{}: no served {} matches {}",
+                    chart.name, dependency.name, dependency.version
+                )));
+            };
+            let file_name = archive_name(&dependency.name, &version);
+            if archives.iter().any(|(f, _)| *f == file_name) {
+                continue;
+            }
+            match self.archive(http, &file_name).await? {
+                Some(archive) => archives.push((file_name, archive)),
+                None => {
+                    return Err(ChartError::Content(format!(
+                        "{file_name} is no longer served"
+                    )))
+                }
+            }
+        }
+        Ok(archives)
+    }
+
+    fn index(&self) -> Result<Repository, ChartError> {
+        let catalog = self
+            .catalog
+            .read()
+            .map_err(|e| ChartError::Internal(e.to_string()))?;
+        Ok(catalog.index(&self.base_url, &self.charts.sources()))
+    }
+
+    async fn subtree(
+        &self,
+        http: &Client,
+        index: &Repository,
+        archive: Vec<u8>,
+        ancestors: &mut Vec<String>,
+    ) -> Result<Tree, ChartError> {
+        let (chart, values) = chart_and_values(&archive).map_err(ChartError::Content)?;
+        let mut vendored = Vec::new();
+        for (file_name, archive) in package::vendored_from_archive(&archive)
+            .map_err(|e| ChartError::Content(format!("{}: {e}", chart.name)))?
+        {
+            let (subchart, _) = chart_and_values(&archive).map_err(|e| {
+                ChartError::Content(format!("{} charts/{file_name}: {e}", chart.name))
+            })?;
+            vendored.push((subchart, archive));
+        }
+
+        ancestors.push(chart.name.clone());
+        let mut dependencies = Vec::new();
+        for dependency in &chart.dependencies {
+            if ancestors.contains(&dependency.name) {
+                dependencies.push((dependency.clone(), None));
+                continue;
+            }
+            let constraint = dependency.version.parse::<Constraint>().ok();
+            let bundled = vendored.iter().find(|(subchart, _)| {
+                subchart.name == dependency.name
+                    && constraint
+                        .as_ref()
+                        .is_some_and(|c| c.matches(&subchart.version))
+            });
+            let archive = match bundled {
+                Some((_, archive)) => Some(archive.clone()),
+                None => match newest(index, dependency) {
+                    Some(version) => {
+                        let file_name = archive_name(&dependency.name, &version);
+                        self.archive(http, &file_name).await?
+                    }
+                    None => None,
+                },
+            };
+            let subtree = match archive {
+                Some(archive) => {
+                    Some(Box::pin(self.subtree(http, index, archive, ancestors)).await?)
+                }
+                None => None,
+            };
+            dependencies.push((dependency.clone(), subtree));
+        }
+        ancestors.pop();
+
+        Ok(Tree {
+            chart,
+            values,
+            dependencies,
+        })
+    }
+}
+
+// The newest served version a dependency's constraint accepts.
+fn newest(index: &Repository, dependency: &Dependency) -> Option<Version> {
+    let constraint = dependency.version.parse::<Constraint>().ok()?;
+    let entries = index.entries.get(&dependency.name)?;
+    constraint
+        .newest(entries.iter().map(|e| &e.version))
+        .cloned()
+}
+
+fn chart_and_values(archive: &[u8]) -> Result<(Chart, Value), String> {
+    let read = |file: &str| package::file_from_archive(archive, file).map_err(|e| e.to_string());
+    let chart: Chart = match read("Chart.yaml")? {
+        Some(chart_yaml) => serde_yaml::from_str(&chart_yaml).map_err(|e| e.to_string())?,
+        None => return Err(String::from("archive does not contain a Chart.yaml")),
+    };
+    let values = match read("values.yaml")? {
+        Some(values) => serde_yaml::from_str(&values).map_err(|e| e.to_string())?,
+        None => Value::Null,
+    };
+    Ok((chart, values))
+}
@@ -0,0 +1,214 @@
+// TLS termination with rustls. The certificate, key and client CA bundle are polled for changes so
+// renewed certificates are picked up without a restart; connections already open keep theirs.
+use std::error::Error;
+use std::fs;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::server::AllowAnyAuthenticatedClient;
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+
+use crate::config::TlsConfig;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+pub fn server_config(tls: &TlsConfig) -> Result<ServerConfig, Box<dyn Error>> {
+    let cert_file = tls
+        .cert_file
+        .as_ref()
+        .ok_or("no TLS certificate configured")?;
+    let key_file = tls.key_file.as_ref().ok_or("no TLS key configured")?;
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let builder = match &tls.client_ca_file {
+        Some(client_ca_file) => {
+            let mut roots = RootCertStore::empty();
+            for cert in certificates(client_ca_file)? {
+                roots.add(&cert)?;
+            }
+            builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder.with_single_cert(certificates(cert_file)?, private_key(key_file)?)?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(config)
+}
+
+fn certificates(path: &Path) -> Result<Vec<Certificate>, Box<dyn Error>> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut reader)
+        .map(|cert| cert.map(|c| Certificate(c.to_vec())))
+        .collect::<Result<_, _>>()
+        .map_err(|e| format!("{}: {e}", path.display()))?;
+
+    if certs.is_empty() {
+        return Err(format!("{} holds no PEM certificates", path.display()).into());
+    }
+    Ok(certs)
+}
+
+fn private_key(path: &Path) -> Result<PrivateKey, Box<dyn Error>> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    let key = rustls_pemfile::private_key(&mut reader)
+        .map_err(|e| format!("{}: {e}", path.display()))?
+        .ok_or_else(|| format!("{} holds no PEM private key", path.display()))?;
+
+    Ok(PrivateKey(key.secret_der().to_vec()))
+}
+
+// Reloads `config` whenever one of the TLS files changes. A broken update is reported and the
+// previous certificate stays in use.
+pub async fn watch(config: RustlsConfig, tls: TlsConfig) {
+    let files: Vec<PathBuf> = [&tls.cert_file, &tls.key_file, &tls.client_ca_file]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect();
+    let mut seen = modified(&files);
+
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        let current = modified(&files);
+        if current == seen {
+            continue;
+        }
+        seen = current;
+
+        match server_config(&tls) {
+            Ok(server_config) => {
+                config.reload_from_config(Arc::new(server_config));
+                println!("reloaded TLS certificate");
+            }
+            Err(err) => eprintln!("could not reload TLS certificate, keeping the old one: {err}"),
+        }
+    }
+}
+
+fn modified(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|f| fs::metadata(f).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    use axum::routing::get;
+    use axum::Router;
+    use axum_server::tls_rustls::RustlsConfig;
+    use axum_server::Handle;
+    use reqwest::{Certificate, Client, Identity};
+
+    use super::server_config;
+    use crate::config::TlsConfig;
+
+    fn testdata(file: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/tls")
+            .join(file)
+    }
+
+    fn tls(client_ca: bool) -> TlsConfig {
+        TlsConfig {
+            cert_file: Some(testdata("server.pem")),
+            key_file: Some(testdata("server.key")),
+            client_ca_file: client_ca.then(|| testdata("ca.pem")),
+        }
+    }
+
+    #[test]
+    fn test_server_config_loads_pem_files() {
+        let config = server_config(&tls(false)).unwrap();
+        assert_eq!(
+            config.alpn_protocols,
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+        );
+        server_config(&tls(true)).unwrap();
+
+        let tmp = tempfile::tempdir().unwrap();
+        let broken = tmp.path().join("broken.pem");
+        fs::write(&broken, "-----BEGIN GARBAGE-----\nnot base64\n").unwrap();
+        for config in [
+            TlsConfig {
+                cert_file: Some(broken.clone()),
+                ..tls(false)
+            },
+            TlsConfig {
+                key_file: Some(broken.clone()),
+                ..tls(false)
+            },
+        ] {
+            let err = server_config(&config).unwrap_err().to_string();
+            assert!(err.starts_with(&format!("{}: ", broken.display())), "{err}");
+        }
+
+        // Well-formed PEM, but not what the file is for.
+        let err = server_config(&TlsConfig {
+            cert_file: Some(testdata("server.key")),
+            ..tls(false)
+        })
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("server.key holds no PEM certificates"));
+        let err = server_config(&TlsConfig {
+            key_file: Some(testdata("server.pem")),
+            ..tls(false)
+        })
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("server.pem holds no PEM private key"));
+        assert!(server_config(&TlsConfig::default()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_client_certificate_is_required() {
+        let config = RustlsConfig::from_config(Arc::new(server_config(&tls(true)).unwrap()));
+        let handle = Handle::new();
+        let server = axum_server::bind_rustls("127.0.0.1:0".parse().unwrap(), config)
+            .handle(handle.clone())
+            .serve(
+                Router::new()
+                    .route("/", get(|| async { "ok" }))
+                    .into_make_service(),
+            );
+        tokio::spawn(server);
+        let addr = handle.listening().await.unwrap();
+        let url = format!("https://localhost:{}/", addr.port());
+
+        let ca = Certificate::from_pem(&fs::read(testdata("ca.pem")).unwrap()).unwrap();
+        let client = |identity: Option<Identity>| {
+            let builder = Client::builder()
+                .use_rustls_tls()
+                .tls_built_in_root_certs(false)
+                .add_root_certificate(ca.clone())
+                .resolve("localhost", addr);
+            match identity {
+                Some(identity) => builder.identity(identity),
+                None => builder,
+            }
+            .build()
+            .unwrap()
+        };
+
+        assert!(client(None).get(&url).send().await.is_err());
+
+        let mut pem = fs::read(testdata("client.key")).unwrap();
+        pem.extend(fs::read(testdata("client.pem")).unwrap());
+        let identity = Identity::from_pem(&pem).unwrap();
+        let resp = client(Some(identity)).get(&url).send().await.unwrap();
+        assert_eq!(resp.text().await.unwrap(), "ok");
+    }
+}
@@ -0,0 +1,383 @@
+// Server-rendered HTML pages for browsing a repository without its API: the catalog, searchable
+// by name, description and keyword, and a page per chart with its README and NOTES.txt.
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Response};
+use pulldown_cmark::{CowStr, Event, Options, Parser, Tag};
+use serde::Deserialize;
+
+use super::{repo, AppState};
+use crate::catalog::archive_name;
+use crate::chart::package;
+use crate::chart::spec::{RepositoryEntry, Version};
+
+const STYLE: &str =
+    "body{font-family:sans-serif;max-width:60rem;margin:auto;padding:1rem;color:#222}\
+a{color:#0b5cad}table{border-collapse:collapse}td,th{padding:.3rem .8rem;text-align:left;\
+border-bottom:1px solid #ddd}pre{background:#f4f4f4;padding:.8rem;overflow-x:auto}\
+.deprecated{color:#a00}.chart{margin:1rem 0}.chart img,h1 img{height:2rem;vertical-align:middle}";
+
+const COPY: &str = "navigator.clipboard.writeText(document.getElementById('install').innerText)";
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Search {
+    q: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Selected {
+    version: Option<Version>, // The newest version when missing.
+}
+
+// The newest version of every chart, filtered by the words searched for.
+pub async fn catalog(
+    State(state): State<Arc<AppState>>,
+    Path(params): Path<HashMap<String, String>>,
+    Query(search): Query<Search>,
+) -> Response {
+    let repo = match repo(&state, &params) {
+        Ok(repo) => repo,
+        Err(err) => return error(StatusCode::NOT_FOUND, &err),
+    };
+    let index = match repo.catalog.read() {
+        Ok(catalog) => catalog.index(&repo.base_url, &repo.charts.sources()),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let prefix = prefix(&params);
+
+    let words: Vec<String> = search.q.split_whitespace().map(str::to_lowercase).collect();
+    let mut body = format!(
+        "<h1>Charts</h1>\n<form><input type=\"search\" name=\"q\" value=\"{}\" \
+         placeholder=\"Search charts\" autofocus> <button>Search</button></form>\n",
+        escape(&search.q)
+    );
+    let mut shown = 0;
+    for (name, entries) in &index.entries {
+        let Some(entry) = entries.iter().max_by(|a, b| a.version.cmp(&b.version)) else {
+            continue;
+        };
+        if !words.iter().all(|word| matches(entry, word)) {
+            continue;
+        }
+        shown += 1;
+        let _ = write!(
+            body,
+            "<div class=\"chart\">{1}<a href=\"{prefix}/ui/charts/{0}\"><strong>{0}</strong></a> {2}",
+            escape(name),
+            icon(entry),
+            escape(&entry.version.to_string())
+        );
+        if entry.deprecated {
+            body.push_str(" <span class=\"deprecated\">deprecated</span>");
+        }
+        let _ = writeln!(body, "<br>{}</div>", escape(&entry.description));
+    }
+    if shown == 0 {
+        body.push_str("<p>No charts found.</p>\n");
+    }
+
+    page("Charts", &body)
+}
+
+// One version of a chart: its metadata, the other versions, how to install it and the README and
+// NOTES.txt from its archive.
+pub async fn chart(
+    State(state): State<Arc<AppState>>,
+    Path(params): Path<HashMap<String, String>>,
+    Query(selected): Query<Selected>,
+) -> Response {
+    let repo = match repo(&state, &params) {
+        Ok(repo) => repo,
+        Err(err) => return error(StatusCode::NOT_FOUND, &err),
+    };
+    let name = &params["name"];
+    let index = match repo.catalog.read() {
+        Ok(catalog) => catalog.index(&repo.base_url, &repo.charts.sources()),
+        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    };
+    let Some(entries) = index.entries.get(name) else {
+        return error(
+            StatusCode::NOT_FOUND,
+            &format!("chart {name} does not exist"),
+        );
+    };
+    let mut entries: Vec<&RepositoryEntry> = entries.iter().collect();
+    entries.sort_by(|a, b| b.version.cmp(&a.version));
+    let entry = match &selected.version {
+        Some(version) => entries.iter().find(|e| e.version == *version),
+        None => entries.first(),
+    };
+    let Some(entry) = entry.copied() else {
+        return error(
+            StatusCode::NOT_FOUND,
+            &format!("chart {name} has no such version"),
+        );
+    };
+
+    let (readme, notes) = match repo
+        .archive(&state.http, &archive_name(name, &entry.version))
+        .await
+    {
+        Ok(Some(archive)) => (
+            package::file_from_archive(&archive, "README.md").unwrap_or_default(),
+            package::file_from_archive(&archive, "templates/NOTES.txt").unwrap_or_default(),
+        ),
+        Ok(None) => (None, None),
+        Err(err) => return error(err.status(), &err.to_string()),
+    };
+    let prefix = prefix(&params);
+    let version = escape(&entry.version.to_string());
+
+    let mut body = format!(
+        "<p><a href=\"{prefix}/ui\">Charts</a></p>\n<h1>{}{} {version}</h1>\n",
+        icon(entry),
+        escape(name)
+    );
+    if entry.deprecated {
+        body.push_str("<p class=\"deprecated\">This chart is deprecated.</p>\n");
+    }
+    if !entry.description.is_empty() {
+        let _ = writeln!(body, "<p>{}</p>", escape(&entry.description));
+    }
+
+    body.push_str("<h2>Install</h2>\n");
+    let alias = escape(&repo.name);
+    let _ = writeln!(
+        body,
+        "<pre id=\"install\">helm repo add {alias} {}\nhelm install {} {alias}/{} --version {version}</pre>\n\
+         <button onclick=\"{COPY}\">Copy</button>",
+        escape(&repo.base_url),
+        escape(name),
+        escape(name)
+    );
+
+    body.push_str("<h2>Details</h2>\n<table>\n");
+    let mut row = |label: &str, value: String| {
+        if !value.is_empty() {
+            let _ = writeln!(body, "<tr><th>{label}</th><td>{value}</td></tr>");
+        }
+    };
+    row("App version", escape(&entry.app_version));
+    row("Type", escape(&entry.type_));
+    row("Kubernetes", escape(&entry.kube_version));
+    row("Home", link(&entry.home, &entry.home));
+    row(
+        "Sources",
+        entry
+            .sources
+            .iter()
+            .map(|source| link(source, source))
+            .collect::<Vec<_>>()
+            .join("<br>"),
+    );
+    row("Keywords", escape(&entry.keywords.join(", ")));
+    row("Created", escape(&entry.created));
+    row(
+        "Maintainers",
+        entry
+            .maintainers
+            .iter()
+            .map(|m| match (m.url.is_empty(), m.email.is_empty()) {
+                (false, _) => link(&m.url, &m.name),
+                (true, false) => link(&format!("mailto:{}", m.email), &m.name),
+                (true, true) => escape(&m.name),
+            })
+            .collect::<Vec<_>>()
+            .join("<br>"),
+    );
+    body.push_str("</table>\n");
+
+    if !entry.dependencies.is_empty() {
+        body.push_str("<h2>Dependencies</h2>\n<table>\n<tr><th>Chart</th><th>Version</th><th>Repository</th><th>Condition</th></tr>\n");
+        for dependency in &entry.dependencies {
+            let chart = if index.entries.contains_key(&dependency.name) {
+                format!(
+                    "<a href=\"{prefix}/ui/charts/{0}\">{0}</a>",
+                    escape(&dependency.name)
+                )
+            } else {
+                escape(&dependency.name)
+            };
+            let _ = writeln!(
+                body,
+                "<tr><td>{chart}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape(&dependency.version),
+                escape(&dependency.repository),
+                escape(&dependency.condition)
+            );
+        }
+        body.push_str("</table>\n");
+    }
+
+    body.push_str("<h2>Versions</h2>\n<table>\n");
+    for other in &entries {
+        let _ = writeln!(
+            body,
+            "<tr><td><a href=\"{prefix}/ui/charts/{0}?version={1}\">{1}</a></td><td>{2}</td><td>{3}</td></tr>",
+            escape(name),
+            escape(&other.version.to_string()),
+            escape(&other.app_version),
+            escape(other.created.get(..10).unwrap_or_default())
+        );
+    }
+    body.push_str("</table>\n");
+
+    if let Some(readme) = readme {
+        let _ = writeln!(body, "<h2>README</h2>\n{}", markdown(&readme));
+    }
+    if let Some(notes) = notes {
+        let _ = writeln!(
+            body,
+            "<h2>Notes</h2>\n<p>Shown by Helm after installing; placeholders are filled in then.</p>\n<pre>{}</pre>",
+            escape(&notes)
+        );
+    }
+
+    page(&format!("{name} {}", entry.version), &body)
+}
+
+// Pages of a repository other than the default one sit under its /repos/:repo prefix.
+fn prefix(params: &HashMap<String, String>) -> String {
+    params
+        .get("repo")
+        .map(|repo| format!("/repos/{}", escape(repo)))
+        .unwrap_or_default()
+}
+
+fn matches(entry: &RepositoryEntry, word: &str) -> bool {
+    entry.name.to_lowercase().contains(word)
+        || entry.description.to_lowercase().contains(word)
+        || entry
+            .keywords
+            .iter()
+            .any(|k| k.to_lowercase().contains(word))
+}
+
+fn icon(entry: &RepositoryEntry) -> String {
+    if entry.icon.is_empty() || !safe_url(&entry.icon) {
+        return String::new();
+    }
+    format!("<img src=\"{}\" alt=\"\"> ", escape(&entry.icon))
+}
+
+fn link(url: &str, text: &str) -> String {
+    if url.is_empty() || !safe_url(url) {
+        return escape(text);
+    }
+    format!("<a href=\"{}\">{}</a>", escape(url), escape(text))
+}
+
+// Charts are uploaded by others, so nothing in them may run script in the reader's browser.
+fn safe_url(url: &str) -> bool {
+    let url = url.trim().to_lowercase();
+    match url.split_once(':') {
+        Some((scheme, _)) if !scheme.contains(['/', '?', '#']) => {
+            matches!(scheme, "http" | "https" | "mailto")
+        }
+        _ => true,
+    }
+}
+
+// CommonMark with GitHub's tables, strikethrough and task lists. HTML in the source is shown as
+// text and links other than http(s), mailto and relative ones are dropped, for the same reason.
+pub fn markdown(source: &str) -> String {
+    let options =
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let events = Parser::new_ext(source, options).map(|event| match event {
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) if !safe_url(&dest_url) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: CowStr::Borrowed(""),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) if !safe_url(&dest_url) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: CowStr::Borrowed(""),
+            title,
+            id,
+        }),
+        event => event,
+    });
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, events);
+    html
+}
+
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn page(title: &str, body: &str) -> Response {
+    Html(format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{}</title>\n<style>{STYLE}</style>\n</head>\n<body>\n{body}</body>\n</html>\n",
+        escape(title)
+    ))
+    .into_response()
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    let title = status.canonical_reason().unwrap_or("Error");
+    (status, page(title, &format!("<p>{}</p>", escape(message)))).into_response()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{escape, markdown};
+
+    #[test]
+    fn test_markdown_is_rendered_without_script() {
+        let html = markdown(
+            "# Install\n\n| Key | Default |\n|-----|---------|\n| `replicas` | 1 |\n\n\
+             <script>alert(1)</script>\n\n[docs](https://example.com) [bad](javascript:alert(1)) \
+             [values](values.yaml)\n",
+        );
+        assert!(html.contains("<h1>Install</h1>"), "{html}");
+        assert!(html.contains("<td><code>replicas</code></td>"), "{html}");
+        assert!(html.contains("&lt;script&gt;"), "{html}");
+        assert!(!html.contains("<script>"), "{html}");
+        assert!(
+            html.contains("<a href=\"https://example.com\">docs</a>"),
+            "{html}"
+        );
+        assert!(html.contains("<a href=\"\">bad</a>"), "{html}");
+        assert!(
+            html.contains("<a href=\"values.yaml\">values</a>"),
+            "{html}"
+        );
+
+        assert_eq!(
+            escape("<a href=\"x\">'&'</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&#39;&amp;&#39;&lt;/a&gt;"
+        );
+    }
+}